@@ -1,16 +1,39 @@
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use crate::compress::Codec;
 use crate::file::File;
 
 #[derive(Debug)]
 pub struct Action {
     pub type_: ActionType,
     pub file: File,
+    pub reason: String,
 }
 
 impl Action {
-    pub fn new(type_: ActionType, file: File) -> Action {
+    pub fn new(type_: ActionType, file: File, reason: &str) -> Action {
         Action {
             type_,
             file,
+            reason: reason.to_string(),
+        }
+    }
+
+    // Moved companions land next to the moved backup. Companions named after
+    // the full path of a compressed backup, such as `a.tar.sha256`, follow
+    // its new name; the others stay where they are.
+    pub fn companion_dest(&self, companion: &Path) -> Option<PathBuf> {
+        match &self.type_ {
+            ActionType::Remove | ActionType::Link { .. } => None,
+            ActionType::Move { dest } => Some(dest.parent().unwrap_or(Path::new("")).join(companion.file_name()?)),
+            ActionType::Compress { codec } => {
+                let suffix = companion.as_os_str().as_bytes().strip_prefix(self.file.path.as_os_str().as_bytes())?;
+                let mut dest = codec.compressed_path(&self.file.path).into_os_string();
+                dest.push(OsStr::from_bytes(suffix));
+                Some(PathBuf::from(dest))
+            }
         }
     }
 }
@@ -18,4 +41,24 @@ impl Action {
 #[derive(Debug, PartialEq)]
 pub enum ActionType {
     Remove,
+    Move { dest: PathBuf },
+    Compress { codec: Codec },
+    Link { dest: PathBuf },
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::file::utils::test_file;
+
+    #[test]
+    fn test_companion_dest() {
+        let mut file = test_file("/backups/a.tar", 1);
+        file.companions = vec![PathBuf::from("/backups/a.tar.sha256"), PathBuf::from("/backups/a.log")];
+        let moved = Action::new(ActionType::Move { dest: PathBuf::from("/archive/a.tar") }, test_file("/backups/a.tar", 1), "");
+        assert_eq!(moved.companion_dest(&file.companions[0]), Some(PathBuf::from("/archive/a.tar.sha256")));
+        let compressed = Action::new(ActionType::Compress { codec: Codec::Zstd }, file, "");
+        assert_eq!(compressed.companion_dest(&compressed.file.companions[0]), Some(PathBuf::from("/backups/a.tar.zst.sha256")));
+        assert_eq!(compressed.companion_dest(&compressed.file.companions[1]), None);
+    }
 }
@@ -0,0 +1,392 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+const BLOCK: usize = 512;
+const EOCD_LEN: u64 = 22;
+const MAX_COMMENT_LEN: u64 = 0xffff;
+const ZIP64_LOCATOR_LEN: u64 = 20;
+const ZIP64_RECORD_LEN: u64 = 56;
+const EXTENDED_TIMESTAMP: u16 = 0x5455;
+
+// Reads the timestamp recorded inside a backup archive: the gzip header MTIME,
+// the newest tar member, or the manifest member of a zip (its newest member
+// when there is no manifest).
+pub fn read_timestamp(path: &Path) -> io::Result<DateTime<Utc>> {
+    let mut file = fs::File::open(path)?;
+    let mut header = [0u8; BLOCK];
+    let len = read_full(&mut file, &mut header)?;
+    file.seek(SeekFrom::Start(0))?;
+    if len >= 10 && header[0..2] == [0x1f, 0x8b] {
+        gzip_mtime(&header)
+    } else if len >= 4 && header[0..4] == *b"PK\x03\x04" {
+        zip_mtime(&mut file)
+    } else if len == BLOCK && &header[257..262] == b"ustar" {
+        tar_mtime(&mut file)
+    } else {
+        Err(invalid("not a gzip, tar or zip archive"))
+    }
+}
+
+fn gzip_mtime(header: &[u8]) -> io::Result<DateTime<Utc>> {
+    let mtime = u32::from_le_bytes(header[4..8].try_into().unwrap());
+    if mtime == 0 {
+        return Err(invalid("gzip header has no MTIME"));
+    }
+    Ok(DateTime::from_timestamp(mtime as i64, 0).unwrap())
+}
+
+fn tar_mtime(file: &mut fs::File) -> io::Result<DateTime<Utc>> {
+    let mut newest = None;
+    let mut header = [0u8; BLOCK];
+    while read_full(file, &mut header)? == BLOCK && header.iter().any(|b| *b != 0) {
+        let size = numeric(&header[124..136])?;
+        let mtime = numeric(&header[136..148])? as i64;
+        newest = newest.max(Some(mtime));
+        let blocks = size.div_ceil(BLOCK as u64);
+        file.seek(SeekFrom::Current((blocks * BLOCK as u64) as i64))?;
+    }
+    newest
+        .and_then(|mtime| DateTime::from_timestamp(mtime, 0))
+        .ok_or_else(|| invalid("tar archive has no members"))
+}
+
+// Only the end of the archive and its central directory are read, since
+// backups can be many gigabytes.
+fn zip_mtime(file: &mut fs::File) -> io::Result<DateTime<Utc>> {
+    let len = file.seek(SeekFrom::End(0))?;
+    let tail_len = len.min(EOCD_LEN + MAX_COMMENT_LEN);
+    let tail = read_at(file, len - tail_len, tail_len)?;
+    let end = (0..tail.len().saturating_sub(EOCD_LEN as usize - 1))
+        .rev()
+        .find(|i| tail[*i..*i + 4] == *b"PK\x05\x06")
+        .ok_or_else(|| invalid("zip archive has no central directory"))?;
+    let mut entries = u16le(&tail, end + 10) as u64;
+    let mut size = u32le(&tail, end + 12) as u64;
+    let mut offset = u32le(&tail, end + 16) as u64;
+    if entries == 0xffff || size == 0xffff_ffff || offset == 0xffff_ffff {
+        (entries, size, offset) = zip64_directory(file, len - tail_len + end as u64)?;
+    }
+    if offset.checked_add(size).is_none_or(|directory_end| directory_end > len) {
+        return Err(invalid("zip central directory is corrupt"));
+    }
+    let data = read_at(file, offset, size)?;
+
+    let mut newest = None;
+    let mut manifest = None;
+    let mut offset = 0;
+    for _ in 0..entries {
+        if offset + 46 > data.len() || data[offset..offset + 4] != *b"PK\x01\x02" {
+            return Err(invalid("zip central directory is corrupt"));
+        }
+        let name_len = u16le(&data, offset + 28) as usize;
+        let extra_len = u16le(&data, offset + 30) as usize;
+        let comment_len = u16le(&data, offset + 32) as usize;
+        let name = String::from_utf8_lossy(data.get(offset + 46..offset + 46 + name_len).unwrap_or_default());
+        let extra = data.get(offset + 46 + name_len..offset + 46 + name_len + extra_len).unwrap_or_default();
+        let time = extended_mtime(extra).or_else(|| dos_datetime(u16le(&data, offset + 14), u16le(&data, offset + 12)));
+        if is_manifest(&name) && manifest.is_none() {
+            manifest = time;
+        }
+        newest = newest.max(time);
+        offset += 46 + name_len + extra_len + comment_len;
+    }
+    manifest.or(newest).ok_or_else(|| invalid("zip archive has no members"))
+}
+
+// The Zip64 locator sits right before the end of central directory record
+// and points to the Zip64 record holding the real count, size and offset.
+fn zip64_directory(file: &mut fs::File, end: u64) -> io::Result<(u64, u64, u64)> {
+    let corrupt = || invalid("zip64 end of central directory is corrupt");
+    let locator = read_at(file, end.checked_sub(ZIP64_LOCATOR_LEN).ok_or_else(corrupt)?, ZIP64_LOCATOR_LEN)?;
+    if locator[0..4] != *b"PK\x06\x07" {
+        return Err(corrupt());
+    }
+    let record = read_at(file, u64le(&locator, 8), ZIP64_RECORD_LEN)?;
+    if record[0..4] != *b"PK\x06\x06" {
+        return Err(corrupt());
+    }
+    Ok((u64le(&record, 32), u64le(&record, 40), u64le(&record, 48)))
+}
+
+fn read_at(file: &mut fs::File, offset: u64, len: u64) -> io::Result<Vec<u8>> {
+    file.seek(SeekFrom::Start(offset))?;
+    let mut data = vec![0; len as usize];
+    if read_full(file, &mut data)? < data.len() {
+        return Err(invalid("zip archive is truncated"));
+    }
+    Ok(data)
+}
+
+fn is_manifest(name: &str) -> bool {
+    let name = name.rsplit('/').next().unwrap().to_lowercase();
+    name == "manifest" || name.starts_with("manifest.")
+}
+
+// The Info-ZIP extended timestamp extra field holds the modification time in
+// UTC seconds, unlike the DOS time, which is local time of unknown zone.
+fn extended_mtime(mut extra: &[u8]) -> Option<DateTime<Utc>> {
+    while extra.len() >= 4 {
+        let (id, len) = (u16le(extra, 0), u16le(extra, 2) as usize);
+        let field = extra.get(4..4 + len)?;
+        if id == EXTENDED_TIMESTAMP && len >= 5 && field[0] & 1 != 0 {
+            return DateTime::from_timestamp(i32::from_le_bytes(field[1..5].try_into().unwrap()) as i64, 0);
+        }
+        extra = &extra[4 + len..];
+    }
+    None
+}
+
+fn dos_datetime(date: u16, time: u16) -> Option<DateTime<Utc>> {
+    let year = ((date >> 9) & 0x7f) as i32 + 1980;
+    let month = ((date >> 5) & 0x0f) as u32;
+    let day = (date & 0x1f) as u32;
+    let datetime = NaiveDate::from_ymd_opt(year, month, day)?.and_hms_opt(
+        (time >> 11) as u32,
+        ((time >> 5) & 0x3f) as u32,
+        (time & 0x1f) as u32 * 2,
+    )?;
+    Some(datetime.and_utc())
+}
+
+// Numbers that do not fit in octal, such as sizes of 8 GiB and more, are
+// written by GNU tar in base-256, flagged by the high bit of the first byte.
+fn numeric(field: &[u8]) -> io::Result<u64> {
+    if field[0] & 0x80 != 0 {
+        if field[0] == 0xff {
+            return Err(invalid("tar header has a negative number"));
+        }
+        return field[1..].iter()
+            .try_fold((field[0] & 0x7f) as u64, |value, byte| value.checked_mul(256).map(|value| value | *byte as u64))
+            .ok_or_else(|| invalid("tar header is corrupt"));
+    }
+    let digits = String::from_utf8_lossy(field);
+    let digits = digits.trim_matches(|c: char| c == '\0' || c == ' ');
+    u64::from_str_radix(digits, 8).map_err(|_| invalid("tar header is corrupt"))
+}
+
+fn u16le(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn u32le(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64le(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+fn read_full(file: &mut fs::File, buf: &mut [u8]) -> io::Result<usize> {
+    let mut len = 0;
+    while len < buf.len() {
+        match file.read(&mut buf[len..])? {
+            0 => break,
+            n => len += n,
+        }
+    }
+    Ok(len)
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+pub mod utils {
+use std::fs;
+use std::path::Path;
+
+pub fn write_gzip(path: &Path, mtime: u32) {
+    let mut data = vec![0x1f, 0x8b, 8, 0];
+    data.extend_from_slice(&mtime.to_le_bytes());
+    data.extend_from_slice(&[0, 3, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    fs::write(path, data).unwrap();
+}
+
+pub fn write_tar(path: &Path, members: &[(&str, &[u8], u64)]) {
+    let mut data = vec![];
+    for (name, content, mtime) in members {
+        let mut header = [0u8; 512];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[100..108].copy_from_slice(b"0000644\0");
+        header[124..136].copy_from_slice(format!("{:011o}\0", content.len()).as_bytes());
+        header[136..148].copy_from_slice(format!("{:011o}\0", mtime).as_bytes());
+        header[156] = b'0';
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+        header[148..156].copy_from_slice(b"        ");
+        let checksum: u32 = header.iter().map(|b| *b as u32).sum();
+        header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+        data.extend_from_slice(&header);
+        data.extend_from_slice(content);
+        data.resize(data.len().div_ceil(512) * 512, 0);
+    }
+    data.resize(data.len() + 1024, 0);
+    fs::write(path, data).unwrap();
+}
+
+fn zip_entries(members: &[(&str, u16, u16)], extra: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    let mut data = vec![];
+    let mut central = vec![];
+    for (name, date, time) in members {
+        let offset = data.len() as u32;
+        data.extend_from_slice(b"PK\x03\x04");
+        data.extend_from_slice(&[20, 0, 0, 0, 0, 0]);
+        data.extend_from_slice(&time.to_le_bytes());
+        data.extend_from_slice(&date.to_le_bytes());
+        data.extend_from_slice(&[0; 12]);
+        data.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        data.extend_from_slice(&(extra.len() as u16).to_le_bytes());
+        data.extend_from_slice(name.as_bytes());
+        data.extend_from_slice(extra);
+
+        central.extend_from_slice(b"PK\x01\x02");
+        central.extend_from_slice(&[20, 0, 20, 0, 0, 0, 0, 0]);
+        central.extend_from_slice(&time.to_le_bytes());
+        central.extend_from_slice(&date.to_le_bytes());
+        central.extend_from_slice(&[0; 12]);
+        central.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        central.extend_from_slice(&(extra.len() as u16).to_le_bytes());
+        central.extend_from_slice(&[0; 10]);
+        central.extend_from_slice(&offset.to_le_bytes());
+        central.extend_from_slice(name.as_bytes());
+        central.extend_from_slice(extra);
+    }
+    (data, central)
+}
+
+// Writes a stored zip whose members carry the given DOS date and time.
+pub fn write_zip(path: &Path, members: &[(&str, u16, u16)]) {
+    write_zip_with_extra(path, members, &[]);
+}
+
+// Same, with the given extra field on every member.
+pub fn write_zip_with_extra(path: &Path, members: &[(&str, u16, u16)], extra: &[u8]) {
+    let (mut data, central) = zip_entries(members, extra);
+    let central_offset = data.len() as u32;
+    data.extend_from_slice(&central);
+    data.extend_from_slice(b"PK\x05\x06");
+    data.extend_from_slice(&[0, 0, 0, 0]);
+    data.extend_from_slice(&(members.len() as u16).to_le_bytes());
+    data.extend_from_slice(&(members.len() as u16).to_le_bytes());
+    data.extend_from_slice(&(central.len() as u32).to_le_bytes());
+    data.extend_from_slice(&central_offset.to_le_bytes());
+    data.extend_from_slice(&[0, 0]);
+    fs::write(path, data).unwrap();
+}
+
+// Same, but with the count, size and offset only in the Zip64 record.
+pub fn write_zip64(path: &Path, members: &[(&str, u16, u16)]) {
+    let (mut data, central) = zip_entries(members, &[]);
+    let central_offset = data.len() as u64;
+    data.extend_from_slice(&central);
+    let record_offset = data.len() as u64;
+    data.extend_from_slice(b"PK\x06\x06");
+    data.extend_from_slice(&44u64.to_le_bytes());
+    data.extend_from_slice(&[45, 0, 45, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    data.extend_from_slice(&(members.len() as u64).to_le_bytes());
+    data.extend_from_slice(&(members.len() as u64).to_le_bytes());
+    data.extend_from_slice(&(central.len() as u64).to_le_bytes());
+    data.extend_from_slice(&central_offset.to_le_bytes());
+    data.extend_from_slice(b"PK\x06\x07");
+    data.extend_from_slice(&[0, 0, 0, 0]);
+    data.extend_from_slice(&record_offset.to_le_bytes());
+    data.extend_from_slice(&[1, 0, 0, 0]);
+    data.extend_from_slice(b"PK\x05\x06");
+    data.extend_from_slice(&[0, 0, 0, 0, 0xff, 0xff, 0xff, 0xff]);
+    data.extend_from_slice(&[0xff; 8]);
+    data.extend_from_slice(&[0, 0]);
+    fs::write(path, data).unwrap();
+}
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::utils::{write_gzip, write_tar, write_zip, write_zip64, write_zip_with_extra};
+    use chrono::TimeZone;
+
+    // 2024-05-23 03:00:00 in DOS format.
+    const DOS_DATE: u16 = (44 << 9) | (5 << 5) | 23;
+    const DOS_TIME: u16 = 3 << 11;
+
+    #[test]
+    fn test_gzip() {
+        let tmp_dir = tempdir::TempDir::new("example").unwrap();
+        let path = tmp_dir.path().join("dump.sql.gz");
+        write_gzip(&path, 1716433201);
+        assert_eq!(read_timestamp(&path).unwrap(), Utc.with_ymd_and_hms(2024, 5, 23, 3, 0, 1).unwrap());
+        write_gzip(&path, 0);
+        assert!(read_timestamp(&path).is_err());
+    }
+
+    #[test]
+    fn test_tar() {
+        let tmp_dir = tempdir::TempDir::new("example").unwrap();
+        let path = tmp_dir.path().join("dump.tar");
+        write_tar(&path, &[
+            ("a", &[1; 700], 1716433201),
+            ("b", b"b", 1716519601),
+            ("c", b"", 1716346801),
+        ]);
+        assert_eq!(read_timestamp(&path).unwrap(), Utc.with_ymd_and_hms(2024, 5, 24, 3, 0, 1).unwrap());
+    }
+
+    #[test]
+    fn test_zip() {
+        let tmp_dir = tempdir::TempDir::new("example").unwrap();
+        let path = tmp_dir.path().join("dump.zip");
+        write_zip(&path, &[("data.sql", DOS_DATE + 1, DOS_TIME), ("backup/manifest.json", DOS_DATE, DOS_TIME)]);
+        assert_eq!(read_timestamp(&path).unwrap(), Utc.with_ymd_and_hms(2024, 5, 23, 3, 0, 0).unwrap());
+        write_zip(&path, &[("a.sql", DOS_DATE + 1, DOS_TIME), ("b.sql", DOS_DATE, DOS_TIME)]);
+        assert_eq!(read_timestamp(&path).unwrap(), Utc.with_ymd_and_hms(2024, 5, 24, 3, 0, 0).unwrap());
+        write_zip64(&path, &[("data.sql", DOS_DATE + 1, DOS_TIME), ("backup/manifest.json", DOS_DATE, DOS_TIME)]);
+        assert_eq!(read_timestamp(&path).unwrap(), Utc.with_ymd_and_hms(2024, 5, 23, 3, 0, 0).unwrap());
+
+        // A comment pushes the end record away from the end of the file.
+        write_zip(&path, &[("a.sql", DOS_DATE, DOS_TIME)]);
+        let mut data = fs::read(&path).unwrap();
+        let comment_len = data.len() - 2;
+        data[comment_len..].copy_from_slice(&1000u16.to_le_bytes());
+        data.extend_from_slice(&[b' '; 1000]);
+        fs::write(&path, data).unwrap();
+        assert_eq!(read_timestamp(&path).unwrap(), Utc.with_ymd_and_hms(2024, 5, 23, 3, 0, 0).unwrap());
+    }
+
+    #[test]
+    fn test_zip_extended_timestamp() {
+        let tmp_dir = tempdir::TempDir::new("example").unwrap();
+        let path = tmp_dir.path().join("dump.zip");
+        // An unrelated field comes first, then the UTC time, an odd second
+        // that the DOS time cannot hold.
+        let mut extra = vec![0x0a, 0, 2, 0, 0, 0, 0x55, 0x54, 5, 0, 1];
+        extra.extend_from_slice(&1716433201u32.to_le_bytes());
+        write_zip_with_extra(&path, &[("a.sql", DOS_DATE, DOS_TIME + (2 << 11))], &extra);
+        assert_eq!(read_timestamp(&path).unwrap(), Utc.with_ymd_and_hms(2024, 5, 23, 3, 0, 1).unwrap());
+
+        assert_eq!(extended_mtime(&[0x55, 0x54, 1, 0, 0]), None);
+        assert_eq!(extended_mtime(&[0x55, 0x54, 9, 0, 1]), None);
+    }
+
+    #[test]
+    fn test_numeric() {
+        assert_eq!(numeric(b"00000001750\0").unwrap(), 1000);
+        let mut base256 = [0u8; 12];
+        base256[0] = 0x80;
+        base256[7] = 2;
+        assert_eq!(numeric(&base256).unwrap(), 8 << 30);
+        base256[0] = 0xff;
+        assert!(numeric(&base256).is_err());
+        assert!(numeric(b"0000000175x\0").is_err());
+    }
+
+    #[test]
+    fn test_not_an_archive() {
+        let tmp_dir = tempdir::TempDir::new("example").unwrap();
+        let path = tmp_dir.path().join("dump.sql");
+        fs::write(&path, "SELECT 1;").unwrap();
+        assert!(read_timestamp(&path).is_err());
+    }
+}
@@ -1,13 +1,184 @@
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
+use regex::bytes::Regex;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::capture::parse_regex;
+use crate::compress::Codec;
+use crate::layout::Layout;
+use crate::rotationplan::Tier;
+use crate::companion::parse_companion;
+use crate::inprogress::parse_duration;
+use crate::precision::parse_format;
+use crate::symlink::SymlinkPolicy;
+use crate::timesource::{DirTime, TimeSource};
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, ValueEnum)]
+pub enum MismatchPolicy {
+    /// Stop the run
+    #[default]
+    Error,
+    /// Leave the file out of the rotation, so it is neither kept nor removed
+    Skip,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Write the actions a run would take to a file instead of taking them
+    Plan {
+        /// File the plan is written to, as JSON
+        #[arg(long)]
+        output: PathBuf,
+
+        #[command(flatten)]
+        args: Box<Args>,
+    },
+    /// Take the actions of a plan written by `plan`, if none of its files changed since
+    Apply {
+        /// Plan written by `plan`
+        plan: PathBuf,
+
+        /// Directory where the journal of the run is written
+        #[arg(long)]
+        journal_dir: Option<PathBuf>,
+
+        #[command(flatten)]
+        removal: Removal,
+    },
+    /// Put back the backups that a previous run moved to the trash or the archive
+    Restore {
+        /// ID of the run to undo, as printed by the run and used to name its journal
+        #[arg(long)]
+        run: String,
+
+        /// Directory holding the journals of previous runs
+        #[arg(long)]
+        journal_dir: Option<PathBuf>,
+    },
+}
 
 #[derive(Parser, Debug)]
+#[command(version, about, long_about = None, args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[command(flatten)]
+    pub args: Args,
+}
+
+#[derive(Parser, Debug, Default)]
 #[command(version, about, long_about = None)]
 pub struct Args {
-    #[arg(required=true)]
-    pub files: Vec<String>,
+    #[arg(required_unless_present = "stdin", conflicts_with = "stdin")]
+    pub files: Vec<PathBuf>,
+
+    /// Read the files to rotate from stdin, one per line
+    #[arg(long)]
+    pub stdin: bool,
 
+    /// Separate the files read from stdin with NUL bytes instead of newlines
+    #[arg(short = '0', long, requires = "stdin")]
+    pub null: bool,
+
+    #[arg(long, value_parser = parse_format)]
+    pub format: Vec<String>,
+
+    /// Match the format against any part of the file name instead of the whole name
+    #[arg(long, requires = "format")]
+    pub unanchored: bool,
+
+    /// Match the format against the path relative to this directory, such as %Y/%m/%d/dump.tar
+    #[arg(long, requires = "format", conflicts_with = "unanchored")]
+    pub root: Option<PathBuf>,
+
+    /// Extract the date with named groups: year, month, day, hour, minute, second, tz, epoch
+    #[arg(long, value_parser = parse_regex, conflicts_with = "format")]
+    pub regex: Option<Regex>,
+
+    /// Detect ISO-8601, YYYYMMDD-HHMMSS, YYYY-MM-DD or Unix epoch timestamps in the file name
+    #[arg(long, conflicts_with_all = ["format", "regex"])]
+    pub detect_date: bool,
+
+    /// Timestamp used when the date is not read from the file name:
+    /// mtime, ctime, atime, btime, archive, sidecar:<key> or xattr:<name>
+    #[arg(long, default_value_t, conflicts_with_all = ["format", "regex", "detect_date"])]
+    pub time_source: TimeSource,
+
+    /// How directory backups are dated when the date is not read from the file name
+    #[arg(long, value_enum, default_value_t, conflicts_with_all = ["format", "regex", "detect_date"])]
+    pub dir_time: DirTime,
+
+    /// Move backups dropped from the rotation into this directory instead of removing them
     #[arg(long)]
-    pub format: Option<String>,
+    pub archive_dir: Option<PathBuf>,
+
+    /// Move backups dropped from the rotation into a .rotation-trash directory next to them
+    #[arg(long, conflicts_with = "archive_dir")]
+    pub trash: bool,
+
+    /// How long trashed backups are kept before later runs delete them, such as 12h or 7d
+    #[arg(long, value_parser = parse_duration, default_value = "7d", requires = "trash")]
+    pub trash_grace: Duration,
+
+    /// Directory where the journal of each run is written, $XDG_STATE_HOME/backup-rotation by default
+    #[arg(long)]
+    pub journal_dir: Option<PathBuf>,
+
+    /// Compress kept backups of the --compress-tiers in place instead of leaving them as they are
+    #[arg(long, value_enum)]
+    pub compress: Option<Codec>,
+
+    /// Tiers whose kept backups are compressed
+    #[arg(long, value_enum, value_delimiter = ',', default_values_t = [Tier::Weekly, Tier::Monthly], requires = "compress")]
+    pub compress_tiers: Vec<Tier>,
+
+    /// Organize kept backups into daily, weekly and monthly directories next to them
+    #[arg(long, value_enum, conflicts_with = "root")]
+    pub layout: Option<Layout>,
+
+    /// File removed together with each backup, such as {path}.sha256 or {stem}.log
+    #[arg(long, value_parser = parse_companion)]
+    pub companion: Vec<String>,
+
+    /// Leave out files modified more recently than this, such as 15m, 2h or 1d
+    #[arg(long, value_parser = parse_duration)]
+    pub min_age: Option<Duration>,
+
+    /// What to do with a file whose date cannot be read
+    #[arg(long, value_enum, default_value_t)]
+    pub on_mismatch: MismatchPolicy,
+
+    /// How symlinks among the files are rotated
+    #[arg(long, value_enum, default_value_t)]
+    pub symlinks: SymlinkPolicy,
+
+    /// Point symlinks to removed backups at the newest kept backup instead of only reporting them
+    #[arg(long)]
+    pub retarget_links: bool,
+
+    /// Review the kept backups and the planned actions, and approve them before they are taken
+    #[arg(long, conflicts_with = "stdin")]
+    pub interactive: bool,
+
+    #[command(flatten)]
+    pub removal: Removal,
+}
+
+// How backups are removed, shared by a run and `apply`.
+#[derive(clap::Args, Debug, Default)]
+pub struct Removal {
+    /// Number of backups removed at the same time, also used to walk large directories being removed
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    pub jobs: u32,
+
+    /// Remove backups one entry at a time, at most this many files and directories per second
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..), conflicts_with = "jobs")]
+    pub max_unlinks_per_sec: Option<u32>,
+
+    /// Remove backups one entry at a time, pausing this long after every 1000 entries, such as 30s or 1m
+    #[arg(long, value_parser = parse_duration, conflicts_with = "jobs")]
+    pub pause_between_batches: Option<Duration>,
 }
 
 #[cfg(test)]
@@ -17,14 +188,242 @@ mod test {
     #[test]
     fn test_args() {
         let args = Args::parse_from(["test", "file1", "file2"]);
-        assert_eq!(args.files, vec!["file1".to_string(), "file2".to_string()]);
-        assert_eq!(args.format, None);
+        assert_eq!(args.files, vec![PathBuf::from("file1"), PathBuf::from("file2")]);
+        assert!(!args.stdin);
+        assert!(!args.null);
+        assert!(args.format.is_empty());
+        assert!(!args.unanchored);
+        assert!(args.root.is_none());
+        assert!(args.regex.is_none());
+        assert!(!args.detect_date);
+        assert_eq!(args.time_source, TimeSource::Mtime);
+        assert_eq!(args.dir_time, DirTime::Itself);
+        assert_eq!(args.on_mismatch, MismatchPolicy::Error);
+        assert_eq!(args.symlinks, SymlinkPolicy::Skip);
+        assert!(!args.retarget_links);
+        assert!(args.min_age.is_none());
+        assert!(args.companion.is_empty());
+        assert!(args.archive_dir.is_none());
+        assert!(!args.trash);
+        assert_eq!(args.trash_grace, Duration::from_secs(7 * 24 * 60 * 60));
+        assert!(args.journal_dir.is_none());
+        assert!(args.compress.is_none());
+        assert_eq!(args.compress_tiers, vec![Tier::Weekly, Tier::Monthly]);
+        assert!(args.layout.is_none());
+        assert!(!args.interactive);
+        assert_eq!(args.removal.jobs, 1);
+        assert!(args.removal.max_unlinks_per_sec.is_none());
+        assert!(args.removal.pause_between_batches.is_none());
+    }
+
+    #[test]
+    fn test_args_with_stdin() {
+        let args = Args::parse_from(["test", "--stdin", "-0"]);
+        assert!(args.files.is_empty());
+        assert!(args.stdin);
+        assert!(args.null);
+        assert!(Args::try_parse_from(["test"]).is_err());
+        assert!(Args::try_parse_from(["test", "file1", "--stdin"]).is_err());
+        assert!(Args::try_parse_from(["test", "-0"]).is_err());
     }
 
     #[test]
     fn test_args_with_format() {
         let args = Args::parse_from(["test", "file1", "file2", "--format", "pg_%Y-%m-%d_%H-%M-%S.tar"]);
-        assert_eq!(args.files, vec!["file1".to_string(), "file2".to_string()]);
-        assert_eq!(args.format, Some("pg_%Y-%m-%d_%H-%M-%S.tar".to_string()));
+        assert_eq!(args.files, vec![PathBuf::from("file1"), PathBuf::from("file2")]);
+        assert_eq!(args.format, vec!["pg_%Y-%m-%d_%H-%M-%S.tar".to_string()]);
+    }
+
+    #[test]
+    fn test_args_with_multiple_formats() {
+        let args = Args::parse_from(["test", "file1", "--format", "pg_%Y-%m-%d.tar", "--format", "pg_%Y%m%d.tar"]);
+        assert_eq!(args.format, vec!["pg_%Y-%m-%d.tar".to_string(), "pg_%Y%m%d.tar".to_string()]);
+    }
+
+    #[test]
+    fn test_args_with_unanchored() {
+        let args = Args::parse_from(["test", "file1", "--format", "%Y-%m-%d", "--unanchored"]);
+        assert!(args.unanchored);
+        assert!(Args::try_parse_from(["test", "file1", "--unanchored"]).is_err());
+    }
+
+    #[test]
+    fn test_args_with_root() {
+        let args = Args::parse_from(["test", "/backups/2024/05/23/dump.tar", "--format", "%Y/%m/%d/dump.tar", "--root", "/backups"]);
+        assert_eq!(args.root, Some(PathBuf::from("/backups")));
+        assert!(Args::try_parse_from(["test", "file1", "--root", "/backups"]).is_err());
+    }
+
+    #[test]
+    fn test_args_with_invalid_format() {
+        assert!(Args::try_parse_from(["test", "file1", "--format", "pg_%m-%d.tar"]).is_err());
+    }
+
+    #[test]
+    fn test_args_with_regex() {
+        let args = Args::parse_from(["test", "file1", "--regex", r"(?<year>\d{4})-(?<month>\d{2})"]);
+        assert_eq!(args.regex.unwrap().as_str(), r"(?<year>\d{4})-(?<month>\d{2})");
+        assert!(Args::try_parse_from(["test", "file1", "--regex", r"(?<month>\d{2})"]).is_err());
+        assert!(Args::try_parse_from(["test", "file1", "--regex", r"(?<year>\d{4})", "--format", "%Y"]).is_err());
+    }
+
+    #[test]
+    fn test_args_with_detect_date() {
+        let args = Args::parse_from(["test", "file1", "--detect-date"]);
+        assert!(args.detect_date);
+        assert!(Args::try_parse_from(["test", "file1", "--detect-date", "--format", "%Y"]).is_err());
+    }
+
+    #[test]
+    fn test_args_with_time_source() {
+        let args = Args::parse_from(["test", "file1", "--time-source", "btime"]);
+        assert_eq!(args.time_source, TimeSource::Btime);
+        let args = Args::parse_from(["test", "file1", "--time-source", "archive"]);
+        assert_eq!(args.time_source, TimeSource::Archive);
+        let args = Args::parse_from(["test", "file1", "--time-source", "sidecar:snapshot.time"]);
+        assert_eq!(args.time_source, TimeSource::Sidecar("snapshot.time".to_string()));
+        let args = Args::parse_from(["test", "file1", "--time-source", "xattr:user.backup.created", "--on-mismatch", "skip"]);
+        assert_eq!(args.time_source, TimeSource::Xattr("user.backup.created".to_string()));
+        assert_eq!(args.on_mismatch, MismatchPolicy::Skip);
+        assert!(Args::try_parse_from(["test", "file1", "--time-source", "mtime", "--detect-date"]).is_err());
+        assert!(Args::try_parse_from(["test", "file1", "--time-source", "birth"]).is_err());
+    }
+
+    #[test]
+    fn test_args_with_dir_time() {
+        let args = Args::parse_from(["test", "file1", "--dir-time", "newest-entry"]);
+        assert_eq!(args.dir_time, DirTime::NewestEntry);
+        let args = Args::parse_from(["test", "file1", "--dir-time", "self"]);
+        assert_eq!(args.dir_time, DirTime::Itself);
+    }
+
+    #[test]
+    fn test_args_with_symlinks() {
+        let args = Args::parse_from(["test", "file1", "--symlinks", "target", "--retarget-links"]);
+        assert_eq!(args.symlinks, SymlinkPolicy::Target);
+        assert!(args.retarget_links);
+        assert!(Args::try_parse_from(["test", "file1", "--symlinks", "resolve"]).is_err());
+    }
+
+    #[test]
+    fn test_args_with_min_age() {
+        let args = Args::parse_from(["test", "file1", "--min-age", "15m"]);
+        assert_eq!(args.min_age, Some(Duration::from_secs(900)));
+        assert!(Args::try_parse_from(["test", "file1", "--min-age", "soon"]).is_err());
+    }
+
+    #[test]
+    fn test_args_with_companion() {
+        let args = Args::parse_from(["test", "file1", "--companion", "{path}.sha256", "--companion", "{stem}.log"]);
+        assert_eq!(args.companion, vec!["{path}.sha256".to_string(), "{stem}.log".to_string()]);
+        assert!(Args::try_parse_from(["test", "file1", "--companion", "{base}.log"]).is_err());
+    }
+
+    #[test]
+    fn test_args_with_archive_dir() {
+        let args = Args::parse_from(["test", "file1", "--archive-dir", "/archive"]);
+        assert_eq!(args.archive_dir, Some(PathBuf::from("/archive")));
+    }
+
+    #[test]
+    fn test_args_with_trash() {
+        let args = Args::parse_from(["test", "file1", "--trash", "--trash-grace", "3d"]);
+        assert!(args.trash);
+        assert_eq!(args.trash_grace, Duration::from_secs(3 * 24 * 60 * 60));
+        assert!(Args::try_parse_from(["test", "file1", "--trash-grace", "3d"]).is_err());
+        assert!(Args::try_parse_from(["test", "file1", "--trash", "--archive-dir", "/archive"]).is_err());
+    }
+
+    #[test]
+    fn test_cli() {
+        let cli = Cli::parse_from(["test", "file1", "--trash"]);
+        assert!(cli.command.is_none());
+        assert_eq!(cli.args.files, vec![PathBuf::from("file1")]);
+        assert!(cli.args.trash);
+        assert!(Cli::try_parse_from(["test"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_with_restore() {
+        let cli = Cli::parse_from(["test", "restore", "--run", "20240523T030001Z", "--journal-dir", "/var/lib/rotation"]);
+        match cli.command {
+            Some(Command::Restore { run, journal_dir }) => {
+                assert_eq!(run, "20240523T030001Z");
+                assert_eq!(journal_dir, Some(PathBuf::from("/var/lib/rotation")));
+            }
+            command => panic!("Unexpected command {:?}", command),
+        }
+        assert!(Cli::try_parse_from(["test", "restore"]).is_err());
+        assert!(Cli::try_parse_from(["test", "--trash", "restore", "--run", "20240523T030001Z"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_with_plan() {
+        let cli = Cli::parse_from(["test", "plan", "--output", "plan.json", "--trash", "file1", "file2"]);
+        match cli.command {
+            Some(Command::Plan { output, args }) => {
+                assert_eq!(output, PathBuf::from("plan.json"));
+                assert_eq!(args.files, vec![PathBuf::from("file1"), PathBuf::from("file2")]);
+                assert!(args.trash);
+            }
+            command => panic!("Unexpected command {:?}", command),
+        }
+        assert!(Cli::try_parse_from(["test", "plan", "file1"]).is_err());
+        assert!(Cli::try_parse_from(["test", "plan", "--output", "plan.json"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_with_apply() {
+        let cli = Cli::parse_from(["test", "apply", "plan.json"]);
+        match cli.command {
+            Some(Command::Apply { plan, journal_dir, removal }) => {
+                assert_eq!(plan, PathBuf::from("plan.json"));
+                assert!(journal_dir.is_none());
+                assert_eq!(removal.jobs, 1);
+            }
+            command => panic!("Unexpected command {:?}", command),
+        }
+        assert!(Cli::try_parse_from(["test", "apply"]).is_err());
+        assert!(Cli::try_parse_from(["test", "apply", "plan.json", "--pause-between-batches", "1m", "--jobs", "2"]).is_err());
+    }
+
+    #[test]
+    fn test_args_with_compress() {
+        let args = Args::parse_from(["test", "file1", "--compress", "zstd", "--compress-tiers", "daily,monthly"]);
+        assert_eq!(args.compress, Some(Codec::Zstd));
+        assert_eq!(args.compress_tiers, vec![Tier::Daily, Tier::Monthly]);
+        assert!(Args::try_parse_from(["test", "file1", "--compress", "xz"]).is_err());
+        assert!(Args::try_parse_from(["test", "file1", "--compress-tiers", "daily"]).is_err());
+    }
+
+    #[test]
+    fn test_args_with_layout() {
+        let args = Args::parse_from(["test", "file1", "--layout", "hardlink"]);
+        assert_eq!(args.layout, Some(Layout::Hardlink));
+        assert!(Args::try_parse_from(["test", "file1", "--layout", "copy"]).is_err());
+        assert!(Args::try_parse_from(["test", "a/dump.tar", "--format", "%Y/dump.tar", "--root", "a", "--layout", "move"]).is_err());
+    }
+
+    #[test]
+    fn test_args_with_interactive() {
+        let args = Args::parse_from(["test", "file1", "--interactive"]);
+        assert!(args.interactive);
+        assert!(Args::try_parse_from(["test", "--stdin", "--interactive"]).is_err());
+    }
+
+    #[test]
+    fn test_args_with_jobs() {
+        let args = Args::parse_from(["test", "file1", "--jobs", "8"]);
+        assert_eq!(args.removal.jobs, 8);
+        assert!(Args::try_parse_from(["test", "file1", "--jobs", "0"]).is_err());
+    }
+
+    #[test]
+    fn test_args_with_throttle() {
+        let args = Args::parse_from(["test", "file1", "--max-unlinks-per-sec", "500", "--pause-between-batches", "30s"]);
+        assert_eq!(args.removal.max_unlinks_per_sec, Some(500));
+        assert_eq!(args.removal.pause_between_batches, Some(Duration::from_secs(30)));
+        assert!(Args::try_parse_from(["test", "file1", "--max-unlinks-per-sec", "0"]).is_err());
+        assert!(Args::try_parse_from(["test", "file1", "--max-unlinks-per-sec", "500", "--jobs", "4"]).is_err());
     }
 }
@@ -0,0 +1,107 @@
+use chrono::{DateTime, FixedOffset, TimeZone, Utc};
+use chrono::format::Parsed;
+use regex::bytes::{Captures, Regex};
+use std::str;
+
+const GROUPS: [&str; 8] = ["year", "month", "day", "hour", "minute", "second", "tz", "epoch"];
+
+pub fn parse_regex(regex: &str) -> Result<Regex, String> {
+    let regex = Regex::new(regex).map_err(|e| e.to_string())?;
+    let names: Vec<&str> = regex.capture_names().flatten().collect();
+    if !names.contains(&"year") && !names.contains(&"epoch") {
+        return Err("regex must contain a `year` or `epoch` named group".to_string());
+    }
+    if let Some(name) = names.iter().find(|name| !GROUPS.contains(name)) {
+        return Err(format!("unknown named group `{}`, expected one of {}", name, GROUPS.join(", ")));
+    }
+    Ok(regex)
+}
+
+pub fn get_date(path: &[u8], regex: &Regex) -> Option<DateTime<Utc>> {
+    from_captures(&regex.captures(path)?)
+}
+
+pub fn from_captures(captures: &Captures) -> Option<DateTime<Utc>> {
+    if let Some(epoch) = captures.name("epoch") {
+        let seconds = str::from_utf8(epoch.as_bytes()).ok()?.parse().ok()?;
+        return Utc.timestamp_opt(seconds, 0).single();
+    }
+
+    let mut parsed = Parsed::new();
+    parsed.set_year(number(captures, "year")?.unwrap_or(1970)).ok()?;
+    parsed.set_month(number(captures, "month")?.unwrap_or(1)).ok()?;
+    parsed.set_day(number(captures, "day")?.unwrap_or(1)).ok()?;
+    parsed.set_hour(number(captures, "hour")?.unwrap_or(0)).ok()?;
+    parsed.set_minute(number(captures, "minute")?.unwrap_or(0)).ok()?;
+    parsed.set_second(number(captures, "second")?.unwrap_or(0)).ok()?;
+    parsed.set_offset(match captures.name("tz") {
+        Some(tz) => offset(str::from_utf8(tz.as_bytes()).ok()?)?,
+        None => 0,
+    }).ok()?;
+    parsed.to_datetime().ok().map(|timestamp| timestamp.with_timezone(&Utc))
+}
+
+// An absent group yields `Some(None)` so that the caller can apply its default,
+// while a group that matched something other than a number fails the parse.
+fn number(captures: &Captures, name: &str) -> Option<Option<i64>> {
+    match captures.name(name) {
+        Some(value) => str::from_utf8(value.as_bytes()).ok()?.parse().ok().map(Some),
+        None => Some(None),
+    }
+}
+
+fn offset(tz: &str) -> Option<i64> {
+    match tz {
+        "Z" | "z" | "UTC" | "utc" => Some(0),
+        _ => tz.parse::<FixedOffset>().ok().map(|offset| offset.local_minus_utc() as i64),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_full_date() {
+        let regex = parse_regex(r"(?<year>\d{4})-(?<month>\d{2})-(?<day>\d{2})_(?<hour>\d{2})-(?<minute>\d{2})-(?<second>\d{2})").unwrap();
+        let date = get_date(b"pg_web01_2024-05-23_03-00-01_job4312.tar", &regex).unwrap();
+        assert_eq!(date, Utc.with_ymd_and_hms(2024, 5, 23, 3, 0, 1).unwrap());
+    }
+
+    #[test]
+    fn test_defaults() {
+        let regex = parse_regex(r"(?<year>\d{4})-(?<month>\d{2})").unwrap();
+        let date = get_date(b"archive_2024-05_x7f3.tar", &regex).unwrap();
+        assert_eq!(date, Utc.with_ymd_and_hms(2024, 5, 1, 0, 0, 0).unwrap());
+    }
+
+    #[test]
+    fn test_timezone() {
+        let regex = parse_regex(r"(?<year>\d{4})(?<month>\d{2})(?<day>\d{2})T(?<hour>\d{2})(?<tz>[+-]\d{4}|Z)").unwrap();
+        let date = get_date(b"db_20240523T03+0200.sql", &regex).unwrap();
+        assert_eq!(date, Utc.with_ymd_and_hms(2024, 5, 23, 1, 0, 0).unwrap());
+        let date = get_date(b"db_20240523T03Z.sql", &regex).unwrap();
+        assert_eq!(date, Utc.with_ymd_and_hms(2024, 5, 23, 3, 0, 0).unwrap());
+    }
+
+    #[test]
+    fn test_epoch() {
+        let regex = parse_regex(r"-(?<epoch>\d+)\.tar").unwrap();
+        let date = get_date(b"backup-1716433201.tar", &regex).unwrap();
+        assert_eq!(date, Utc.with_ymd_and_hms(2024, 5, 23, 3, 0, 1).unwrap());
+    }
+
+    #[test]
+    fn test_no_match() {
+        let regex = parse_regex(r"(?<year>\d{4})-(?<month>\d{2})-(?<day>\d{2})").unwrap();
+        assert!(get_date(b"pg_latest.tar", &regex).is_none());
+        assert!(get_date(b"pg_2024-13-01.tar", &regex).is_none());
+    }
+
+    #[test]
+    fn test_invalid_regex() {
+        assert!(parse_regex(r"(?<month>\d{2})").is_err());
+        assert!(parse_regex(r"(?<year>\d{4})(?<host>\w+)").is_err());
+        assert!(parse_regex(r"(?<year>\d{4}").is_err());
+    }
+}
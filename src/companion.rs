@@ -0,0 +1,132 @@
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use crate::compress::strip_suffix;
+
+const PLACEHOLDERS: [&str; 4] = ["{path}", "{dir}", "{name}", "{stem}"];
+
+pub fn parse_companion(pattern: &str) -> Result<String, String> {
+    let mut rest = pattern;
+    while let Some(start) = rest.find('{') {
+        let placeholder = PLACEHOLDERS.iter().find(|placeholder| rest[start..].starts_with(**placeholder))
+            .ok_or_else(|| format!("Unknown placeholder in {}, expected {}", pattern, PLACEHOLDERS.join(", ")))?;
+        rest = &rest[start + placeholder.len()..];
+    }
+    if !["{path}", "{name}", "{stem}"].iter().any(|placeholder| pattern.contains(placeholder)) {
+        return Err(format!("Companion pattern {} must contain {{path}}, {{name}} or {{stem}}", pattern));
+    }
+    Ok(pattern.to_string())
+}
+
+// Patterns without `{path}` or `{dir}` are relative to the backup's directory,
+// so `{stem}.log` is looked up next to the backup.
+pub fn expand(pattern: &str, path: &Path) -> PathBuf {
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let mut expanded = OsString::new();
+    let mut rest = pattern;
+    while let Some(start) = rest.find('{') {
+        expanded.push(&rest[..start]);
+        rest = &rest[start..];
+        let placeholder = PLACEHOLDERS.iter().find(|placeholder| rest.starts_with(**placeholder)).unwrap();
+        match *placeholder {
+            "{path}" => expanded.push(path),
+            "{dir}" => expanded.push(dir),
+            "{name}" => expanded.push(path.file_name().unwrap_or_default()),
+            _ => expanded.push(path.file_stem().unwrap_or_default()),
+        }
+        rest = &rest[placeholder.len()..];
+    }
+    expanded.push(rest);
+    let expanded = PathBuf::from(expanded);
+    match pattern.contains("{path}") || pattern.contains("{dir}") {
+        true => expanded,
+        false => dir.join(expanded),
+    }
+}
+
+// Compression leaves companions such as `{stem}.log` where they are, so a
+// compressed backup also expands its patterns against its original name.
+fn expansions(pattern: &str, path: &Path) -> Vec<PathBuf> {
+    let original = path.file_name()
+        .and_then(|name| name.to_str())
+        .and_then(strip_suffix)
+        .map(|name| path.with_file_name(name));
+    std::iter::once(path).chain(original.as_deref()).map(|path| expand(pattern, path)).collect()
+}
+
+pub fn find(path: &Path, patterns: &[String]) -> Vec<PathBuf> {
+    patterns.iter()
+        .flat_map(|pattern| expansions(pattern, path))
+        .filter(|companion| companion != path && companion.symlink_metadata().is_ok())
+        .collect()
+}
+
+// Every path that is a companion of another of the files, expanded once so
+// that each file is looked up rather than compared with all the others.
+pub fn companions_of(files: &[PathBuf], patterns: &[String]) -> HashSet<PathBuf> {
+    if patterns.is_empty() {
+        return HashSet::new();
+    }
+    files.iter()
+        .flat_map(|file| patterns.iter().flat_map(move |pattern| expansions(pattern, file).into_iter().map(move |companion| (file, companion))))
+        .filter(|(file, companion)| companion != *file)
+        .map(|(_, companion)| companion)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_parse_companion() {
+        assert!(parse_companion("{path}.sha256").is_ok());
+        assert!(parse_companion("{dir}/logs/{stem}.log").is_ok());
+        assert!(parse_companion("{dir}/checksums").is_err());
+        assert!(parse_companion("{base}.log").is_err());
+    }
+
+    #[test]
+    fn test_expand() {
+        let path = Path::new("/backups/pg_2024-05-23.tar");
+        assert_eq!(expand("{path}.sha256", path), Path::new("/backups/pg_2024-05-23.tar.sha256"));
+        assert_eq!(expand("{stem}.log", path), Path::new("/backups/pg_2024-05-23.log"));
+        assert_eq!(expand("sig/{name}.sig", path), Path::new("/backups/sig/pg_2024-05-23.tar.sig"));
+        assert_eq!(expand("{dir}/logs/{stem}.log", path), Path::new("/backups/logs/pg_2024-05-23.log"));
+        assert_eq!(expand("{stem}.log", Path::new("pg.tar")), Path::new("pg.log"));
+    }
+
+    #[test]
+    fn test_find() {
+        let tmp_dir = TempDir::new("example").unwrap();
+        let backup = tmp_dir.path().join("pg_2024-05-23.tar");
+        fs::File::create(&backup).unwrap();
+        fs::File::create(tmp_dir.path().join("pg_2024-05-23.tar.sha256")).unwrap();
+        let patterns = vec!["{path}.sha256".to_string(), "{stem}.log".to_string()];
+        assert_eq!(find(&backup, &patterns), vec![tmp_dir.path().join("pg_2024-05-23.tar.sha256")]);
+
+        let files = vec![backup.clone(), tmp_dir.path().join("pg_2024-05-23.tar.sha256")];
+        let companions = companions_of(&files, &patterns);
+        assert!(companions.contains(&files[1]));
+        assert!(!companions.contains(&files[0]));
+        assert!(companions_of(&files, &[]).is_empty());
+    }
+
+    #[test]
+    fn test_find_compressed() {
+        let tmp_dir = TempDir::new("example").unwrap();
+        let backup = tmp_dir.path().join("pg_2024-05-23.tar.gz");
+        fs::File::create(&backup).unwrap();
+        fs::File::create(tmp_dir.path().join("pg_2024-05-23.tar.gz.sha256")).unwrap();
+        fs::File::create(tmp_dir.path().join("pg_2024-05-23.log")).unwrap();
+        let patterns = vec!["{path}.sha256".to_string(), "{stem}.log".to_string()];
+        assert_eq!(find(&backup, &patterns), vec![
+            tmp_dir.path().join("pg_2024-05-23.tar.gz.sha256"),
+            tmp_dir.path().join("pg_2024-05-23.log"),
+        ]);
+        assert!(companions_of(std::slice::from_ref(&backup), &patterns).contains(&tmp_dir.path().join("pg_2024-05-23.log")));
+    }
+}
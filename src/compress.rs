@@ -0,0 +1,221 @@
+use clap::ValueEnum;
+use flate2::read::GzDecoder;
+use flate2::GzBuilder;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::display::display;
+use crate::rotationplan::Tier;
+use crate::suffix;
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Codec {
+    Gzip,
+    Zstd,
+}
+
+impl Codec {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Codec::Gzip => "gz",
+            Codec::Zstd => "zst",
+        }
+    }
+
+    pub fn compressed_path(&self, path: &Path) -> PathBuf {
+        suffix::append(path, self.extension())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Compression {
+    pub codec: Codec,
+    pub tiers: Vec<Tier>,
+}
+
+pub fn is_compressed(path: &Path) -> bool {
+    codec_of(path).is_some()
+}
+
+pub fn codec_of(path: &Path) -> Option<Codec> {
+    let extension = path.extension().and_then(|extension| extension.to_str());
+    [Codec::Gzip, Codec::Zstd].into_iter().find(|codec| extension == Some(codec.extension()))
+}
+
+// Lets a compressed backup still match the format written for its original name.
+pub fn strip_suffix(name: &str) -> Option<&str> {
+    [Codec::Gzip, Codec::Zstd].iter()
+        .find_map(|codec| name.strip_suffix(&format!(".{}", codec.extension())))
+}
+
+// The original is only removed once the compressed copy has been synced and
+// decompressed back to the same bytes.
+pub fn compress(path: &Path, codec: Codec) -> io::Result<PathBuf> {
+    let dest = codec.compressed_path(path);
+    if dest.symlink_metadata().is_ok() {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} already exists", display(&dest))));
+    }
+    let tmp = suffix::tmp_path(&dest);
+
+    let mut input = fs::File::open(path)?;
+    let modified = input.metadata()?.modified()?;
+    let output = fs::File::create(&tmp)?;
+    let output = match codec {
+        Codec::Gzip => {
+            // The gzip header records the time too, for --time-source archive.
+            let mtime = modified.duration_since(UNIX_EPOCH).map(|mtime| mtime.as_secs() as u32).unwrap_or(0);
+            let mut encoder = GzBuilder::new().mtime(mtime).write(output, flate2::Compression::default());
+            io::copy(&mut input, &mut encoder)?;
+            encoder.finish()?
+        }
+        Codec::Zstd => {
+            let mut encoder = zstd::Encoder::new(output, 0)?;
+            io::copy(&mut input, &mut encoder)?;
+            encoder.finish()?
+        }
+    };
+    output.set_modified(modified)?;
+    copy_xattrs(path, &tmp)?;
+    output.sync_all()?;
+
+    if !decompresses_to(&tmp, codec, path)? {
+        fs::remove_file(&tmp)?;
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("compressed copy of {} does not match", display(path))));
+    }
+    fs::rename(&tmp, &dest)?;
+    if let Some(parent) = dest.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        fs::File::open(parent)?.sync_all()?;
+    }
+    fs::remove_file(path)?;
+    Ok(dest)
+}
+
+// Undoes `compress`: the compressed file is only removed once the
+// decompressed copy has been synced and checked against it.
+pub fn decompress_to(compressed: &Path, dest: &Path) -> io::Result<()> {
+    let codec = codec_of(compressed)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("{} is not compressed", display(compressed))))?;
+    if dest.symlink_metadata().is_ok() {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} already exists", display(dest))));
+    }
+    let tmp = suffix::tmp_path(dest);
+
+    let input = fs::File::open(compressed)?;
+    let modified = input.metadata()?.modified()?;
+    let mut decoded: Box<dyn Read> = match codec {
+        Codec::Gzip => Box::new(GzDecoder::new(input)),
+        Codec::Zstd => Box::new(zstd::Decoder::new(input)?),
+    };
+    let mut output = fs::File::create(&tmp)?;
+    io::copy(&mut decoded, &mut output)?;
+    output.set_modified(modified)?;
+    copy_xattrs(compressed, &tmp)?;
+    output.sync_all()?;
+
+    if !decompresses_to(compressed, codec, &tmp)? {
+        fs::remove_file(&tmp)?;
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("decompressed copy of {} does not match", display(compressed))));
+    }
+    fs::rename(&tmp, dest)?;
+    fs::remove_file(compressed)
+}
+
+// Keeps the attributes read by --time-source xattr:<name>.
+fn copy_xattrs(path: &Path, dest: &Path) -> io::Result<()> {
+    let Ok(names) = xattr::list(path) else { return Ok(()) };
+    for name in names {
+        if let Some(value) = xattr::get(path, &name)? {
+            xattr::set(dest, &name, &value)?;
+        }
+    }
+    Ok(())
+}
+
+fn decompresses_to(compressed: &Path, codec: Codec, original: &Path) -> io::Result<bool> {
+    let compressed = fs::File::open(compressed)?;
+    let mut decoded: Box<dyn Read> = match codec {
+        Codec::Gzip => Box::new(GzDecoder::new(compressed)),
+        Codec::Zstd => Box::new(zstd::Decoder::new(compressed)?),
+    };
+    let mut original = io::BufReader::new(fs::File::open(original)?);
+    let mut buf_a = [0u8; 8192];
+    let mut buf_b = [0u8; 8192];
+    loop {
+        let len = original.read(&mut buf_a)?;
+        if len == 0 {
+            return Ok(decoded.read(&mut buf_b)? == 0);
+        }
+        if decoded.read_exact(&mut buf_b[..len]).is_err() || buf_a[..len] != buf_b[..len] {
+            return Ok(false);
+        }
+    }
+}
+
+#[cfg(test)]
+pub fn decompress(path: &Path, codec: Codec) -> Vec<u8> {
+    let file = fs::File::open(path).unwrap();
+    let mut data = vec![];
+    match codec {
+        Codec::Gzip => GzDecoder::new(file).read_to_end(&mut data).unwrap(),
+        Codec::Zstd => zstd::Decoder::new(file).unwrap().read_to_end(&mut data).unwrap(),
+    };
+    data
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_compress() {
+        let tmp_dir = TempDir::new("example").unwrap();
+        for codec in [Codec::Gzip, Codec::Zstd] {
+            let path = tmp_dir.path().join("pg_2024-05-23.tar");
+            fs::write(&path, "SELECT 1;\n".repeat(1000)).unwrap();
+            let modified = fs::metadata(&path).unwrap().modified().unwrap();
+            let dest = compress(&path, codec).unwrap();
+
+            assert_eq!(dest, codec.compressed_path(&path));
+            assert!(!path.exists());
+            assert_eq!(decompress(&dest, codec), "SELECT 1;\n".repeat(1000).into_bytes());
+            assert_eq!(fs::metadata(&dest).unwrap().modified().unwrap(), modified);
+        }
+        let gzip = tmp_dir.path().join("pg_2024-05-23.tar.gz");
+        assert!(crate::archive::read_timestamp(&gzip).is_ok());
+    }
+
+    #[test]
+    fn test_compress_keeps_xattrs() {
+        let tmp_dir = TempDir::new("example").unwrap();
+        let path = tmp_dir.path().join("a.tar");
+        fs::write(&path, "dump").unwrap();
+        if xattr::set(&path, "user.backup.created", b"2024-05-23T03:00:01Z").is_err() {
+            // The filesystem holding the temporary directory has no user xattrs.
+            return;
+        }
+        let dest = compress(&path, Codec::Zstd).unwrap();
+        assert_eq!(xattr::get(&dest, "user.backup.created").unwrap(), Some(b"2024-05-23T03:00:01Z".to_vec()));
+    }
+
+    #[test]
+    fn test_compress_existing() {
+        let tmp_dir = TempDir::new("example").unwrap();
+        let path = tmp_dir.path().join("a.tar");
+        fs::write(&path, "dump").unwrap();
+        fs::write(tmp_dir.path().join("a.tar.gz"), "other").unwrap();
+        assert!(compress(&path, Codec::Gzip).is_err());
+        assert!(path.exists());
+    }
+
+    #[test]
+    fn test_suffix() {
+        assert!(is_compressed(Path::new("a.tar.gz")));
+        assert!(is_compressed(Path::new("a.tar.zst")));
+        assert!(!is_compressed(Path::new("a.tar")));
+        assert_eq!(strip_suffix("pg_2024-05-23.tar.zst"), Some("pg_2024-05-23.tar"));
+        assert_eq!(strip_suffix("pg_2024-05-23.tar"), None);
+    }
+}
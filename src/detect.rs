@@ -0,0 +1,98 @@
+use chrono::{DateTime, Datelike, Utc};
+use regex::bytes::Regex;
+use std::ops::Range;
+use std::sync::LazyLock;
+
+use crate::capture::from_captures;
+
+// Ordered from the most to the least specific, so that a full ISO-8601
+// timestamp is not also reported as the bare date it starts with.
+static PATTERNS: LazyLock<Vec<Regex>> = LazyLock::new(|| {
+    [
+        r"(?<year>\d{4})-(?<month>\d{2})-(?<day>\d{2})T(?<hour>\d{2}):?(?<minute>\d{2}):?(?<second>\d{2})(?:\.\d+)?(?<tz>Z|[+-]\d{2}:?\d{2})?",
+        r"(?<year>\d{4})(?<month>\d{2})(?<day>\d{2})[-_T](?<hour>\d{2})(?<minute>\d{2})(?<second>\d{2})",
+        r"(?<year>\d{4})-(?<month>\d{2})-(?<day>\d{2})[-_ ](?<hour>\d{2})[-:](?<minute>\d{2})[-:](?<second>\d{2})",
+        r"(?<year>\d{4})-(?<month>\d{2})-(?<day>\d{2})",
+        r"(?<epoch>\d{10})\d{3}",
+        r"(?<epoch>\d{10})",
+    ]
+    .iter()
+    .map(|pattern| Regex::new(pattern).unwrap())
+    .collect()
+});
+
+pub fn get_date(path: &[u8]) -> Result<DateTime<Utc>, String> {
+    let mut candidates: Vec<(Range<usize>, DateTime<Utc>)> = vec![];
+    for pattern in PATTERNS.iter() {
+        for captures in pattern.captures_iter(path) {
+            let range = captures.get(0).unwrap().range();
+            if !on_digit_boundary(path, &range) || overlaps(&candidates, &range) {
+                continue;
+            }
+            if let Some(date) = from_captures(&captures).filter(|date| date.year() <= 2100) {
+                candidates.push((range, date));
+            }
+        }
+    }
+
+    match candidates.len() {
+        0 => Err("No date found".to_string()),
+        1 => Ok(candidates[0].1),
+        _ => {
+            candidates.sort_by_key(|(range, _)| range.start);
+            let found: Vec<String> = candidates.iter()
+                .map(|(range, _)| String::from_utf8_lossy(&path[range.clone()]).into_owned())
+                .collect();
+            Err(format!("Ambiguous date, found {}", found.join(", ")))
+        }
+    }
+}
+
+fn on_digit_boundary(bytes: &[u8], range: &Range<usize>) -> bool {
+    let before = range.start > 0 && bytes[range.start - 1].is_ascii_digit();
+    let after = range.end < bytes.len() && bytes[range.end].is_ascii_digit();
+    !before && !after
+}
+
+fn overlaps(candidates: &[(Range<usize>, DateTime<Utc>)], range: &Range<usize>) -> bool {
+    candidates.iter().any(|(other, _)| other.start < range.end && range.start < other.end)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_detect() {
+        let cases = [
+            ("pg_2024-05-23T03:00:01Z.tar", Utc.with_ymd_and_hms(2024, 5, 23, 3, 0, 1)),
+            ("pg_2024-05-23T030001+0200.tar", Utc.with_ymd_and_hms(2024, 5, 23, 1, 0, 1)),
+            ("pg_20240523_030001.tar", Utc.with_ymd_and_hms(2024, 5, 23, 3, 0, 1)),
+            ("pg_20240523-030001.tar", Utc.with_ymd_and_hms(2024, 5, 23, 3, 0, 1)),
+            ("pg_2024-05-23_03-00-01.tar", Utc.with_ymd_and_hms(2024, 5, 23, 3, 0, 1)),
+            ("db_2024-05-23.sql.gz", Utc.with_ymd_and_hms(2024, 5, 23, 0, 0, 0)),
+            ("backup-1716433201.tar", Utc.with_ymd_and_hms(2024, 5, 23, 3, 0, 1)),
+            ("backup-1716433201123.tar", Utc.with_ymd_and_hms(2024, 5, 23, 3, 0, 1)),
+            ("web01_job42_2024-05-23.tar", Utc.with_ymd_and_hms(2024, 5, 23, 0, 0, 0)),
+        ];
+        for (path, expected) in cases {
+            assert_eq!(get_date(path.as_bytes()), Ok(expected.unwrap()), "{}", path);
+        }
+        assert_eq!(get_date(b"caf\xe9_2024-05-23.tar"), Ok(Utc.with_ymd_and_hms(2024, 5, 23, 0, 0, 0).unwrap()));
+    }
+
+    #[test]
+    fn test_no_date() {
+        assert!(get_date(b"pg_latest.tar").is_err());
+        assert!(get_date(b"pg_2024-13-45.tar").is_err());
+        assert!(get_date(b"job_12345678901234.tar").is_err());
+    }
+
+    #[test]
+    fn test_ambiguous() {
+        let error = get_date(b"diff_2024-05-22_2024-05-23.tar").unwrap_err();
+        assert!(error.contains("2024-05-22, 2024-05-23"), "{}", error);
+        assert!(get_date(b"pg_2024-05-23_1716433201.tar").is_err());
+    }
+}
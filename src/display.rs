@@ -0,0 +1,31 @@
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+// Unlike `Path::display`, bytes that are not valid UTF-8 are shown as `\xNN`
+// escapes rather than replaced, and backslashes are doubled, so that two
+// distinct paths never print alike.
+pub fn display(path: &Path) -> String {
+    let mut shown = String::new();
+    for chunk in path.as_os_str().as_bytes().utf8_chunks() {
+        shown.push_str(&chunk.valid().replace('\\', "\\\\"));
+        for byte in chunk.invalid() {
+            shown.push_str(&format!("\\x{:02x}", byte));
+        }
+    }
+    shown
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::ffi::OsStr;
+
+    #[test]
+    fn test_display() {
+        assert_eq!(display(Path::new("/backups/pg_2024-05-23.tar")), "/backups/pg_2024-05-23.tar");
+        assert_eq!(display(Path::new("/backups/caf\u{e9}.tar")), "/backups/caf\u{e9}.tar");
+        let latin1 = OsStr::from_bytes(b"/backups/caf\xe9_2024-05-23.tar");
+        assert_eq!(display(Path::new(latin1)), "/backups/caf\\xe9_2024-05-23.tar");
+        assert_eq!(display(Path::new("/backups/caf\\xe9_2024-05-23.tar")), "/backups/caf\\\\xe9_2024-05-23.tar");
+    }
+}
@@ -1,28 +1,198 @@
-use std::fs::{remove_file, remove_dir_all};
-use std::path::Path;
+use std::fs::{create_dir_all, hard_link, read_dir, remove_dir, remove_file, remove_dir_all, rename, symlink_metadata};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use crate::action::ActionType;
 use crate::plan::Plan;
 use crate::action::Action;
+use crate::display::display;
+use crate::compress::compress;
+use crate::file::File;
+use crate::journal::Journal;
+use crate::layout::same_file;
+use crate::parallel::for_each_ordered;
+use crate::relocate::move_path;
+use crate::suffix;
+use crate::throttle::{self, Limiter, Throttle};
 
-pub fn execute_plan(plan: Plan) {
-    for action in plan {
-        execute_action(&action);
+// With a throttle, backups are removed one at a time whatever `jobs` is.
+pub fn execute_plan(plan: Plan, mut journal: Option<&mut Journal>, jobs: usize, throttle: Option<Throttle>) {
+    let mut limiter = throttle.map(Limiter::new);
+    let mut actions = plan.into_iter().peekable();
+    while let Some(action) = actions.next() {
+        if jobs > 1 && limiter.is_none() && action.type_ == ActionType::Remove {
+            let mut batch = vec![action];
+            while let Some(action) = actions.next_if(|action| action.type_ == ActionType::Remove) {
+                batch.push(action);
+            }
+            remove_batch(&batch, jobs, &mut journal);
+            continue;
+        }
+        execute_action(&action, limiter.as_mut());
+        record(&mut journal, &action);
     }
 }
 
-fn execute_action(action: &Action){
-    match action.type_ {
-        ActionType::Remove => {
-            if Path::new(&action.file.path).exists() {
-                println!("Removing file: {}", action.file.path);
-                let filetype = Path::new(&action.file.path).metadata().unwrap().file_type();
-                match filetype.is_dir() {
-                    true => remove_dir_all(action.file.path.clone()).unwrap(),
-                    false => remove_file(action.file.path.clone()).unwrap(),
+fn record(journal: &mut Option<&mut Journal>, action: &Action) {
+    if let Some(journal) = journal.as_deref_mut() {
+        journal.record(action).unwrap_or_else(|e| panic!("Failed to write the journal: {}", e));
+    }
+}
+
+// Consecutive removals run on up to `jobs` threads. Their output, journal
+// entries and errors still follow the order of the plan, and no removal
+// starts once one has failed.
+fn remove_batch(batch: &[Action], jobs: usize, journal: &mut Option<&mut Journal>) {
+    let tree_jobs = (jobs / batch.len()).max(1);
+    let failed = AtomicBool::new(false);
+    let mut errors = vec![];
+    for_each_ordered(batch, jobs, |action| {
+        if failed.load(Ordering::SeqCst) {
+            return None;
+        }
+        let mut lines = vec![];
+        let result = remove(&action.file, tree_jobs, None, &mut |line| lines.push(line));
+        if result.is_err() {
+            failed.store(true, Ordering::SeqCst);
+        }
+        Some((lines, result))
+    }, |action, removed| {
+        let Some((lines, result)) = removed else { return };
+        lines.iter().for_each(|line| println!("{}", line));
+        match result {
+            Ok(()) => {
+                if let Some(root) = &action.file.root {
+                    prune_empty_parents(&action.file.path, root);
                 }
+                record(journal, action);
             }
+            Err(e) => errors.push(format!("Failed to remove {}: {}", display(&action.file.path), e)),
+        }
+    });
+    if !errors.is_empty() {
+        panic!("{}", errors.join("\n"));
+    }
+}
+
+fn execute_action(action: &Action, limiter: Option<&mut Limiter>){
+    match &action.type_ {
+        ActionType::Remove => {
+            remove(&action.file, 1, limiter, &mut |line| println!("{}", line))
+                .unwrap_or_else(|e| panic!("Failed to remove {}: {}", display(&action.file.path), e));
         },
+        ActionType::Move { dest } => {
+            if symlink_metadata(&action.file.path).is_ok() {
+                println!("Moving file: {} -> {}", display(&action.file.path), display(dest));
+                move_path(&action.file.path, dest)
+                    .unwrap_or_else(|e| panic!("Failed to move {}: {}", display(&action.file.path), e));
+            }
+            move_companions(action);
+        },
+        ActionType::Compress { codec } => {
+            if action.file.path.is_file() {
+                println!("Compressing file: {} -> {}", display(&action.file.path), display(&codec.compressed_path(&action.file.path)));
+                compress(&action.file.path, *codec)
+                    .unwrap_or_else(|e| panic!("Failed to compress {}: {}", display(&action.file.path), e));
+                move_companions(action);
+            }
+            return;
+        },
+        ActionType::Link { dest } => {
+            if !same_file(&action.file.path, dest) {
+                println!("Linking file: {} -> {}", display(&action.file.path), display(dest));
+                link(&action.file.path, dest)
+                    .unwrap_or_else(|e| panic!("Failed to link {}: {}", display(&action.file.path), e));
+            }
+            return;
+        },
+    }
+    if let Some(root) = &action.file.root {
+        prune_empty_parents(&action.file.path, root);
+    }
+}
+
+fn move_companions(action: &Action) {
+    for companion in &action.file.companions {
+        let Some(companion_dest) = action.companion_dest(companion) else { continue };
+        if companion.exists() {
+            println!("Moving companion file: {} -> {}", display(companion), display(&companion_dest));
+            move_path(companion, &companion_dest)
+                .unwrap_or_else(|e| panic!("Failed to move {}: {}", display(companion), e));
+        }
+    }
+}
+
+fn remove(file: &File, jobs: usize, mut limiter: Option<&mut Limiter>, log: &mut dyn FnMut(String)) -> io::Result<()> {
+    // A symlink is removed itself, never the backup it points to.
+    if let Ok(metadata) = symlink_metadata(&file.path) {
+        log(format!("Removing file: {}", display(&file.path)));
+        match (limiter.as_deref_mut(), metadata.is_dir()) {
+            (Some(limiter), _) => throttle::remove(&file.path, limiter)?,
+            (None, true) => remove_tree(&file.path, jobs)?,
+            (None, false) => remove_file(&file.path)?,
+        }
+    }
+    for companion in &file.companions {
+        if companion.exists() {
+            log(format!("Removing companion file: {}", display(companion)));
+            match limiter.as_deref_mut() {
+                Some(limiter) => throttle::remove(companion, limiter)?,
+                None => remove_file(companion)?,
+            }
+        }
+    }
+    Ok(())
+}
+
+// Walks the entries of a directory on up to `jobs` threads, sharing them out
+// among the subdirectories when there are fewer entries than threads.
+fn remove_tree(path: &Path, jobs: usize) -> io::Result<()> {
+    if jobs <= 1 {
+        return remove_dir_all(path);
+    }
+    let entries = read_dir(path)?.map(|entry| entry.map(|entry| entry.path())).collect::<io::Result<Vec<PathBuf>>>()?;
+    let entry_jobs = (jobs / entries.len().max(1)).max(1);
+    let mut result = Ok(());
+    for_each_ordered(&entries, jobs, |entry| -> io::Result<()> {
+        match symlink_metadata(entry)?.is_dir() {
+            true => remove_tree(entry, entry_jobs),
+            false => remove_file(entry),
+        }
+    }, |_, removed| {
+        if result.is_ok() {
+            result = removed;
+        }
+    });
+    result?;
+    remove_dir(path)
+}
+
+// The link is created beside `dest` and renamed over it, replacing a stale
+// entry of the same name.
+fn link(path: &Path, dest: &Path) -> std::io::Result<()> {
+    if let Some(parent) = dest.parent() {
+        create_dir_all(parent)?;
+    }
+    let tmp = suffix::tmp_path(dest);
+    let _ = remove_file(&tmp);
+    hard_link(path, &tmp)?;
+    rename(&tmp, dest)
+}
+
+pub fn prune_empty_parents(path: &Path, root: &Path) {
+    let mut parent = path.parent();
+    while let Some(dir) = parent {
+        if dir == root || !dir.starts_with(root) {
+            break;
+        }
+        let is_empty = read_dir(dir).map(|mut entries| entries.next().is_none()).unwrap_or(false);
+        if !is_empty {
+            break;
+        }
+        println!("Removing empty directory: {}", display(dir));
+        remove_dir(dir).unwrap();
+        parent = dir.parent();
     }
 }
 
@@ -30,68 +200,249 @@ fn execute_action(action: &Action){
 mod tests {
 
 use super::*;
-use crate::file::utils::test_file;
+use crate::file::utils::test_file_at;
 use tempdir::TempDir;
-use std::fs::{File, create_dir};
-
-fn pathbuf_to_str(pb: std::path::PathBuf) -> String {
-    pb.into_os_string().into_string().unwrap()
-}
+use crate::compress::Codec;
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::fs::{File, create_dir, create_dir_all};
 
 #[test]
 fn test_remove_file() {
     let tmp_dir = TempDir::new("example").unwrap();
     let tmp_path = tmp_dir.path().join("a");
-    let file = test_file(pathbuf_to_str(tmp_path.clone()).as_str(), 1);
+    let file = test_file_at(&tmp_path, 1);
     let f = File::create(tmp_path.clone()).unwrap();
     drop(f);
     assert!(tmp_path.exists());
-    let action = Action::new(ActionType::Remove, file.clone());
-    execute_action(&action);
+    let action = Action::new(ActionType::Remove, file.clone(), "");
+    execute_action(&action, None);
 
     assert!(!tmp_path.exists());
 }
 
+#[test]
+fn test_remove_non_utf8_file() {
+    let tmp_dir = TempDir::new("example").unwrap();
+    let tmp_path = tmp_dir.path().join(OsStr::from_bytes(b"caf\xe9_2024-05-23.tar"));
+    File::create(&tmp_path).unwrap();
+    let action = Action::new(ActionType::Remove, test_file_at(&tmp_path, 1), "");
+    execute_action(&action, None);
+
+    assert!(!tmp_path.exists());
+}
+
+#[test]
+fn test_remove_symlink() {
+    let tmp_dir = TempDir::new("example").unwrap();
+    let target = tmp_dir.path().join("a");
+    let link = tmp_dir.path().join("latest");
+    create_dir(&target).unwrap();
+    File::create(target.join("dump.tar")).unwrap();
+    std::os::unix::fs::symlink(&target, &link).unwrap();
+    let action = Action::new(ActionType::Remove, test_file_at(&link, 1), "");
+    execute_action(&action, None);
+
+    assert!(link.symlink_metadata().is_err());
+    assert!(target.join("dump.tar").exists());
+}
+
 #[test]
 fn test_remove_dir() {
     let tmp_dir = TempDir::new("example").unwrap();
     let tmp_path = tmp_dir.path().join("a");
-    let file = test_file(pathbuf_to_str(tmp_path.clone()).as_str(), 1);
+    let file = test_file_at(&tmp_path, 1);
     create_dir(tmp_path.clone()).unwrap();
     assert!(tmp_path.exists());
-    let action = Action::new(ActionType::Remove, file.clone());
-    execute_action(&action);
+    let action = Action::new(ActionType::Remove, file.clone(), "");
+    execute_action(&action, None);
 
     assert!(!tmp_path.exists());
 }
 
+#[test]
+fn test_move() {
+    let tmp_dir = TempDir::new("example").unwrap();
+    let tmp_path = tmp_dir.path().join("a");
+    let sidecar_path = tmp_dir.path().join("a.sha256");
+    let dest = tmp_dir.path().join("archive/a");
+    File::create(&tmp_path).unwrap();
+    File::create(&sidecar_path).unwrap();
+    let mut file = test_file_at(&tmp_path, 1);
+    file.companions.push(sidecar_path.clone());
+    execute_action(&Action::new(ActionType::Move { dest: dest.clone() }, file, ""), None);
+
+    assert!(!tmp_path.exists());
+    assert!(!sidecar_path.exists());
+    assert!(dest.exists());
+    assert!(tmp_dir.path().join("archive/a.sha256").exists());
+}
+
+#[test]
+fn test_compress() {
+    let tmp_dir = TempDir::new("example").unwrap();
+    let tmp_path = tmp_dir.path().join("a.tar");
+    std::fs::write(&tmp_path, "dump").unwrap();
+    let checksum = tmp_dir.path().join("a.tar.sha256");
+    let log = tmp_dir.path().join("a.log");
+    File::create(&checksum).unwrap();
+    File::create(&log).unwrap();
+    let mut file = test_file_at(&tmp_path, 1);
+    file.companions = vec![checksum.clone(), log.clone()];
+    execute_action(&Action::new(ActionType::Compress { codec: Codec::Gzip }, file, ""), None);
+
+    assert!(!tmp_path.exists());
+    assert!(!checksum.exists());
+    assert!(tmp_dir.path().join("a.tar.gz.sha256").exists());
+    assert!(log.exists());
+    assert_eq!(crate::compress::decompress(&tmp_dir.path().join("a.tar.gz"), Codec::Gzip), b"dump");
+}
+
+#[test]
+fn test_link() {
+    let tmp_dir = TempDir::new("example").unwrap();
+    let tmp_path = tmp_dir.path().join("a.tar");
+    let dest = tmp_dir.path().join("weekly/a.tar");
+    File::create(&tmp_path).unwrap();
+    execute_action(&Action::new(ActionType::Link { dest: dest.clone() }, test_file_at(&tmp_path, 1), ""), None);
+    execute_action(&Action::new(ActionType::Link { dest: dest.clone() }, test_file_at(&tmp_path, 1), ""), None);
+
+    assert!(tmp_path.exists());
+    assert!(same_file(&tmp_path, &dest));
+}
+
+#[test]
+fn test_remove_companions() {
+    let tmp_dir = TempDir::new("example").unwrap();
+    let tmp_path = tmp_dir.path().join("a");
+    let sidecar_path = tmp_dir.path().join("a.json");
+    File::create(tmp_path.clone()).unwrap();
+    File::create(sidecar_path.clone()).unwrap();
+    let mut file = test_file_at(&tmp_path, 1);
+    file.companions.push(sidecar_path.clone());
+    execute_action(&Action::new(ActionType::Remove, file, ""), None);
+
+    assert!(!tmp_path.exists());
+    assert!(!sidecar_path.exists());
+}
+
+#[test]
+fn test_remove_prunes_empty_parents() {
+    let tmp_dir = TempDir::new("example").unwrap();
+    let root = tmp_dir.path().join("backups");
+    create_dir_all(root.join("2024/05/23")).unwrap();
+    create_dir_all(root.join("2024/06/01")).unwrap();
+    File::create(root.join("2024/05/23/dump.tar")).unwrap();
+    let mut file = test_file_at(&root.join("2024/05/23"), 1);
+    file.root = Some(root.clone());
+    execute_action(&Action::new(ActionType::Remove, file, ""), None);
+
+    assert!(!root.join("2024/05").exists());
+    assert!(root.join("2024/06/01").exists());
+
+    let mut file = test_file_at(&root.join("2024/06/01"), 1);
+    file.root = Some(root.clone());
+    execute_action(&Action::new(ActionType::Remove, file, ""), None);
+
+    assert!(!root.join("2024").exists());
+    assert!(root.exists());
+}
+
 #[test]
 fn test_execute_plan() {
     let tmp_dir = TempDir::new("example").unwrap();
     let files = [
-        test_file(pathbuf_to_str(tmp_dir.path().join("a").clone()).as_str(), 1),
-        test_file(pathbuf_to_str(tmp_dir.path().join("b").clone()).as_str(), 2),
-        test_file(pathbuf_to_str(tmp_dir.path().join("c").clone()).as_str(), 3),
-        test_file(pathbuf_to_str(tmp_dir.path().join("d").clone()).as_str(), 4),
+        test_file_at(&tmp_dir.path().join("a"), 1),
+        test_file_at(&tmp_dir.path().join("b"), 2),
+        test_file_at(&tmp_dir.path().join("c"), 3),
+        test_file_at(&tmp_dir.path().join("d"), 4),
     ];
     for item in files.iter() {
         let f = File::create(item.path.clone()).unwrap();
         drop(f);
     }
     let plan = vec![
-        Action::new(ActionType::Remove, files[0].clone()),
-        Action::new(ActionType::Remove, files[3].clone()),
+        Action::new(ActionType::Remove, files[0].clone(), ""),
+        Action::new(ActionType::Remove, files[3].clone(), ""),
     ];
     files.iter().for_each(|f| {
         assert!(Path::new(&f.path).exists());
     });
 
-    execute_plan(plan);
+    let mut journal = Journal::create(tmp_dir.path(), "run").unwrap();
+    execute_plan(plan, Some(&mut journal), 1, None);
 
+    let entries = std::fs::read_to_string(tmp_dir.path().join("run.jsonl")).unwrap();
+    assert_eq!(entries.lines().count(), 3);
     assert!(!tmp_dir.path().join("a").exists());
     assert!(tmp_dir.path().join("b").exists());
     assert!(tmp_dir.path().join("c").exists());
     assert!(!tmp_dir.path().join("d").exists());
 }
 
+#[test]
+fn test_execute_plan_with_jobs() {
+    let tmp_dir = TempDir::new("example").unwrap();
+    let mut plan = vec![];
+    for i in 0..6 {
+        let dir = tmp_dir.path().join(format!("snapshot_{}", i));
+        for sub in ["base/1", "base/2", "global"] {
+            create_dir_all(dir.join(sub)).unwrap();
+            File::create(dir.join(sub).join("data")).unwrap();
+        }
+        plan.push(Action::new(ActionType::Remove, test_file_at(&dir, i), ""));
+    }
+    plan.push(Action::new(ActionType::Move { dest: tmp_dir.path().join("archive/kept") }, test_file_at(&tmp_dir.path().join("kept"), 7), ""));
+    File::create(tmp_dir.path().join("kept")).unwrap();
+
+    let mut journal = Journal::create(tmp_dir.path(), "run").unwrap();
+    execute_plan(plan, Some(&mut journal), 4, None);
+
+    let entries = std::fs::read_to_string(tmp_dir.path().join("run.jsonl")).unwrap();
+    let paths: Vec<String> = entries.lines().skip(1)
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["path"].as_str().unwrap().to_string())
+        .collect();
+    let mut expected: Vec<String> = (0..6).map(|i| display(&tmp_dir.path().join(format!("snapshot_{}", i)))).collect();
+    expected.push(display(&tmp_dir.path().join("kept")));
+    assert_eq!(paths, expected);
+    for i in 0..6 {
+        assert!(!tmp_dir.path().join(format!("snapshot_{}", i)).exists());
+    }
+    assert!(tmp_dir.path().join("archive/kept").exists());
+}
+
+#[test]
+fn test_remove_tree() {
+    let tmp_dir = TempDir::new("example").unwrap();
+    let dir = tmp_dir.path().join("snapshot");
+    create_dir_all(dir.join("a/b/c")).unwrap();
+    File::create(dir.join("a/b/c/data")).unwrap();
+    File::create(dir.join("a/data")).unwrap();
+    std::os::unix::fs::symlink(tmp_dir.path().join("outside"), dir.join("a/link")).unwrap();
+    create_dir(tmp_dir.path().join("outside")).unwrap();
+    File::create(tmp_dir.path().join("outside/data")).unwrap();
+    remove_tree(&dir, 8).unwrap();
+
+    assert!(!dir.exists());
+    assert!(tmp_dir.path().join("outside/data").exists());
+}
+
+#[test]
+fn test_remove_batch_error() {
+    let tmp_dir = TempDir::new("example").unwrap();
+    let a = tmp_dir.path().join("a");
+    let b = tmp_dir.path().join("b");
+    File::create(&a).unwrap();
+    File::create(&b).unwrap();
+    // A companion that is a directory cannot be removed as a file.
+    create_dir(tmp_dir.path().join("a.sha256")).unwrap();
+    let mut file = test_file_at(&a, 1);
+    file.companions.push(tmp_dir.path().join("a.sha256"));
+    let plan = vec![Action::new(ActionType::Remove, file, ""), Action::new(ActionType::Remove, test_file_at(&b, 2), "")];
+
+    let error = std::panic::catch_unwind(|| execute_plan(plan, None, 2, None)).unwrap_err();
+    assert!(error.downcast_ref::<String>().unwrap().starts_with(&format!("Failed to remove {}:", display(&a))));
+    assert!(!a.exists());
+}
+
 }
@@ -1,13 +1,26 @@
-use chrono::{DateTime, Utc, NaiveDateTime, TimeZone};
-use std::fs;
+use chrono::{DateTime, Utc, ParseResult, TimeZone, Weekday};
+use chrono::format::{parse, parse_and_remainder, Item, Parsed, StrftimeItems};
 use std::hash::{Hasher, Hash};
-use std::path::Path;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
 
+use regex::bytes::Regex;
+
+use crate::archive;
+use crate::capture;
+use crate::compress;
 use crate::dated::Dated;
+use crate::detect;
+use crate::display::display;
+use crate::sidecar;
+use crate::timesource::{DirTime, TimeSource};
 
 #[derive(Debug, Clone)]
 pub struct File {
-    pub path: String,
+    pub path: PathBuf,
+    pub format: Option<String>,
+    pub root: Option<PathBuf>,
+    pub companions: Vec<PathBuf>,
     timestamp: DateTime<Utc>,
 }
 
@@ -24,24 +37,105 @@ impl Hash for File {
 }
 
 impl File {
-    pub fn new(path: String, timestamp: DateTime<Utc>) -> File {
+    pub fn new(path: PathBuf, timestamp: DateTime<Utc>) -> File {
         File {
             path,
+            format: None,
+            root: None,
+            companions: vec![],
             timestamp,
         }
     }
 
-    pub fn from_fs(path: String) -> Result<File, ()> {
-        let timestamp = fs::metadata(&path).unwrap().modified().unwrap();
+    pub fn from_fs(path: PathBuf, source: &TimeSource, dir_time: DirTime) -> Result<File, String> {
+        let timestamp = dir_time.read(&path, source)
+            .map_err(|e| format!("Failed to read the {} of {}: {}", source, display(&path), e))?;
         Ok(File::new(path, timestamp.into()))
     }
 
-    pub fn from_path(path: String, format: &str) -> Result<File, ()> {
-        let pathbuf = Path::new(&path);
-        let filename = pathbuf.file_name().unwrap();
-        let timestamp = get_date(filename.to_str().unwrap(), format);
+    pub fn from_archive(path: PathBuf) -> Result<File, String> {
+        let timestamp = archive::read_timestamp(&path)
+            .map_err(|e| format!("Failed to read the archive timestamp of {}: {}", display(&path), e))?;
         Ok(File::new(path, timestamp))
     }
+
+    pub fn from_sidecar(path: PathBuf, key: &str) -> Result<File, String> {
+        let sidecar = sidecar::find(&path)
+            .ok_or_else(|| format!("No .json or .meta sidecar found for {}", display(&path)))?;
+        let timestamp = sidecar::read_timestamp(&sidecar, key)?;
+        let mut file = File::new(path, timestamp);
+        file.companions.push(sidecar);
+        Ok(file)
+    }
+
+    pub fn from_path(path: PathBuf, formats: &[String]) -> Result<File, ()> {
+        File::from_formats(path, formats, get_date)
+    }
+
+    pub fn from_path_unanchored(path: PathBuf, formats: &[String]) -> Result<File, ()> {
+        File::from_formats(path, formats, search_date)
+    }
+
+    // The format is matched against the path relative to the root, and the
+    // backup is the directory holding the deepest date component, so that
+    // `%Y/%m/%d/dump.tar` rotates `2024/05/23` as a whole.
+    pub fn from_root(path: PathBuf, root: &Path, formats: &[String]) -> Result<File, ()> {
+        let relative = path.strip_prefix(root).map_err(|_| ())?;
+        for format in formats {
+            if let Ok(timestamp) = get_date(&relative.to_string_lossy(), format) {
+                let depth = date_depth(format);
+                let mut unit = root.to_path_buf();
+                unit.extend(relative.components().take(depth));
+                let mut file = File::new(unit, timestamp);
+                file.format = Some(format.clone());
+                file.root = Some(root.to_path_buf());
+                return Ok(file);
+            }
+        }
+        Err(())
+    }
+
+    // chrono only parses `str`, so bytes that are not valid UTF-8 are replaced;
+    // the date still parses as long as they fall outside the formatted part.
+    fn from_formats(
+        path: PathBuf,
+        formats: &[String],
+        get_date: fn(&str, &str) -> ParseResult<DateTime<Utc>>,
+    ) -> Result<File, ()> {
+        let filename = path.file_name().unwrap().to_string_lossy().into_owned();
+        let names = [Some(filename.as_str()), compress::strip_suffix(&filename)];
+        for (format, name) in formats.iter().flat_map(|format| names.iter().flatten().map(move |name| (format, name))) {
+            if let Ok(timestamp) = get_date(name, format) {
+                let mut file = File::new(path, timestamp);
+                file.format = Some(format.clone());
+                return Ok(file);
+            }
+        }
+        Err(())
+    }
+
+    pub fn from_regex(path: PathBuf, regex: &Regex) -> Result<File, ()> {
+        let filename = path.file_name().unwrap().as_bytes();
+        match capture::get_date(filename, regex) {
+            Some(timestamp) => Ok(File::new(path, timestamp)),
+            None => Err(()),
+        }
+    }
+
+    pub fn from_detected(path: PathBuf) -> Result<File, String> {
+        let filename = path.file_name().unwrap().as_bytes();
+        let timestamp = detect::get_date(filename).map_err(|e| format!("{} in {}", e, display(&path)))?;
+        Ok(File::new(path, timestamp))
+    }
+
+    // The part of the path kept when the backup is moved elsewhere: the date
+    // directories under --root, or else the file name.
+    pub fn relative_path(&self) -> &Path {
+        match &self.root {
+            Some(root) => self.path.strip_prefix(root).unwrap_or(&self.path),
+            None => Path::new(self.path.file_name().unwrap_or(self.path.as_os_str())),
+        }
+    }
 }
 
 impl Dated for File {
@@ -50,28 +144,78 @@ impl Dated for File {
     }
 }
 
-fn from_naive_datetime(path: &str, format: &str) -> DateTime<Utc> {
-    let timestamp = match NaiveDateTime::parse_from_str(path, format) {
-        Ok(timestamp) => timestamp,
-        Err(_) => panic!("Failed to parse path: {}", path)
-    };
-    Utc.from_utc_datetime(&timestamp)
+fn get_date(path: &str, format: &str) -> ParseResult<DateTime<Utc>> {
+    let mut parsed = Parsed::new();
+    parse(&mut parsed, path, StrftimeItems::new(format))?;
+    to_date(&mut parsed)
+}
+
+// Finds the first substring of the path that matches the format, so that
+// prefixes and suffixes such as `pg_` or `.tar.gz` need not be spelled out.
+fn search_date(path: &str, format: &str) -> ParseResult<DateTime<Utc>> {
+    let mut result = get_date(path, format);
+    for (start, _) in path.char_indices() {
+        if result.is_ok() {
+            break;
+        }
+        let mut parsed = Parsed::new();
+        result = parse_and_remainder(&mut parsed, &path[start..], StrftimeItems::new(format))
+            .and_then(|_| to_date(&mut parsed));
+    }
+    result
 }
 
-fn from_datetime(path: &str, format: &str) -> DateTime<Utc> {
-    let timestamp = match DateTime::parse_from_str(path, format) {
-        Ok(timestamp) => timestamp,
-        Err(_) => panic!("Failed to parse path: {}", path)
-    };
-    timestamp.with_timezone(&Utc)
+fn to_date(parsed: &mut Parsed) -> ParseResult<DateTime<Utc>> {
+    fill_missing(parsed)?;
+    match parsed.offset() {
+        Some(_) => Ok(parsed.to_datetime()?.with_timezone(&Utc)),
+        None => Ok(Utc.from_utc_datetime(&parsed.to_naive_datetime_with_offset(0)?)),
+    }
+}
 
+// Coarse formats such as `%Y-%m-%d`, `%Y-%m` or `%G-W%V` leave fields unset;
+// they resolve to the start of the period they name.
+fn fill_missing(parsed: &mut Parsed) -> ParseResult<()> {
+    if parsed.timestamp().is_some() {
+        return Ok(());
+    }
+    if parsed.isoweek().is_some() {
+        if parsed.weekday().is_none() {
+            parsed.set_weekday(Weekday::Mon)?;
+        }
+    } else if parsed.ordinal().is_none() {
+        if parsed.month().is_none() {
+            parsed.set_month(1)?;
+        }
+        if parsed.day().is_none() {
+            parsed.set_day(1)?;
+        }
+    }
+    if parsed.hour_div_12().is_none() && parsed.hour_mod_12().is_none() {
+        parsed.set_hour(0)?;
+    }
+    if parsed.minute().is_none() {
+        parsed.set_minute(0)?;
+    }
+    if parsed.second().is_none() {
+        parsed.set_second(0)?;
+    }
+    Ok(())
 }
 
-fn get_date(path: &str, format: &str) -> DateTime<Utc> {
-    match format.contains("%z") {
-        true => from_datetime(path, format),
-        false => from_naive_datetime(path, format),
+// The number of path components up to the one holding the last date
+// specifier. A literal `%%` is not a specifier.
+fn date_depth(format: &str) -> usize {
+    let mut slashes = 0;
+    let mut depth = 0;
+    for item in StrftimeItems::new(format) {
+        match item {
+            Item::Literal(text) | Item::Space(text) => slashes += text.matches('/').count(),
+            Item::OwnedLiteral(text) | Item::OwnedSpace(text) => slashes += text.matches('/').count(),
+            _ => depth = slashes + 1,
+        }
     }
+    depth
 }
 
 #[cfg(test)]
@@ -82,7 +226,14 @@ use std::collections::VecDeque;
 pub fn test_file(name: &str, days: usize) -> File {
     let base_time = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
     File::new(
-        name.to_string(),
+        PathBuf::from(name),
+        base_time + chrono::Duration::days(days as i64),
+    )
+}
+pub fn test_file_at(path: &Path, days: usize) -> File {
+    let base_time = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+    File::new(
+        path.to_path_buf(),
         base_time + chrono::Duration::days(days as i64),
     )
 }
@@ -96,6 +247,8 @@ pub fn to_files(v: Vec<usize>) -> VecDeque<File> {
 mod test {
     use super::*;
     use chrono::Datelike;
+    use std::ffi::OsStr;
+    use std::fs;
 
     #[test]
     fn test_file_from_str1() {
@@ -129,9 +282,9 @@ mod test {
             Utc.with_ymd_and_hms(2024, 3, 13, 3, 0, 1).unwrap(),
             Utc.with_ymd_and_hms(2024, 3, 25, 3, 0, 1).unwrap()
         ];
-        let format = "pg_%Y-%m-%d_%H-%M-%S.tar".to_string();
+        let format = vec!["pg_%Y-%m-%d_%H-%M-%S.tar".to_string()];
         input.iter().zip(expected.iter()).for_each(|(i, e)| {
-            let file = File::from_path(i.to_string(), &format).unwrap();
+            let file = File::from_path(PathBuf::from(i), &format).unwrap();
             assert_eq!(file.get_date(), *e);
         });
     }
@@ -154,13 +307,106 @@ mod test {
             Utc.with_ymd_and_hms(2024, 5, 27, 3, 0, 2).unwrap(),
             Utc.with_ymd_and_hms(2024, 5, 28, 3, 0, 1).unwrap()
         ];
-        let format = "influx_%Y-%m-%d_%H-%M-%S".to_string();
+        let format = vec!["influx_%Y-%m-%d_%H-%M-%S".to_string()];
         input.iter().zip(expected.iter()).for_each(|(i, e)| {
-            let file = File::from_path(i.to_string(), &format).unwrap();
+            let file = File::from_path(PathBuf::from(i), &format).unwrap();
             assert_eq!(file.get_date(), *e);
         });
     }
 
+    #[test]
+    fn test_file_from_str_multiple_formats() {
+        let formats = vec![
+            "pg_%Y-%m-%d_%H-%M-%S.tar".to_string(),
+            "postgres-%Y%m%dT%H%M%S.tar".to_string(),
+        ];
+        let old = File::from_path(PathBuf::from("pg_2024-02-17_03-00-01.tar"), &formats).unwrap();
+        assert_eq!(old.get_date(), Utc.with_ymd_and_hms(2024, 2, 17, 3, 0, 1).unwrap());
+        assert_eq!(old.format, Some(formats[0].clone()));
+
+        let new = File::from_path(PathBuf::from("postgres-20240523T030001.tar"), &formats).unwrap();
+        assert_eq!(new.get_date(), Utc.with_ymd_and_hms(2024, 5, 23, 3, 0, 1).unwrap());
+        assert_eq!(new.format, Some(formats[1].clone()));
+
+        assert!(File::from_path(PathBuf::from("mysql_2024-05-23.tar"), &formats).is_err());
+    }
+
+    #[test]
+    fn test_file_from_coarse_formats() {
+        let cases = [
+            ("db_2024-05-23.sql.gz", "db_%Y-%m-%d.sql.gz", Utc.with_ymd_and_hms(2024, 5, 23, 0, 0, 0)),
+            ("archive_2024-05.tar", "archive_%Y-%m.tar", Utc.with_ymd_and_hms(2024, 5, 1, 0, 0, 0)),
+            ("weekly_2024-W21.tar", "weekly_%G-W%V.tar", Utc.with_ymd_and_hms(2024, 5, 20, 0, 0, 0)),
+            ("pg_2024-05-23_03.tar", "pg_%Y-%m-%d_%H.tar", Utc.with_ymd_and_hms(2024, 5, 23, 3, 0, 0)),
+            ("pg_2024-05-23_03-00+0200.tar", "pg_%Y-%m-%d_%H-%M%z.tar", Utc.with_ymd_and_hms(2024, 5, 23, 1, 0, 0)),
+            ("backup-1716433201.tar", "backup-%s.tar", Utc.with_ymd_and_hms(2024, 5, 23, 3, 0, 1)),
+        ];
+        for (path, format, expected) in cases {
+            let file = File::from_path(PathBuf::from(path), &[format.to_string()]).unwrap();
+            assert_eq!(file.get_date(), expected.unwrap());
+        }
+    }
+
+    #[test]
+    fn test_file_from_compressed_path() {
+        let formats = vec!["pg_%Y-%m-%d.tar".to_string()];
+        let expected = Utc.with_ymd_and_hms(2024, 5, 23, 0, 0, 0).unwrap();
+        for path in ["pg_2024-05-23.tar.gz", "pg_2024-05-23.tar.zst"] {
+            let file = File::from_path(PathBuf::from(path), &formats).unwrap();
+            assert_eq!(file.get_date(), expected);
+            assert_eq!(file.path, Path::new(path));
+        }
+        assert!(File::from_path(PathBuf::from("pg_2024-05-23.tar.xz"), &formats).is_err());
+    }
+
+    #[test]
+    fn test_file_from_path_unanchored() {
+        let formats = vec!["%Y-%m-%d_%H-%M-%S".to_string()];
+        let expected = Utc.with_ymd_and_hms(2024, 5, 23, 3, 0, 1).unwrap();
+        for path in ["pg_2024-05-23_03-00-01.tar.gz", "pg_2024-05-23_03-00-01.tar", "2024-05-23_03-00-01"] {
+            let file = File::from_path_unanchored(PathBuf::from(path), &formats).unwrap();
+            assert_eq!(file.get_date(), expected);
+            assert_eq!(file.format, Some(formats[0].clone()));
+        }
+        assert!(File::from_path(PathBuf::from("pg_2024-05-23_03-00-01.tar.gz"), &formats).is_err());
+        assert!(File::from_path_unanchored(PathBuf::from("pg_2024-05-23.tar"), &formats).is_err());
+    }
+
+    #[test]
+    fn test_file_from_root() {
+        let formats = vec!["%Y/%m/%d/dump.tar".to_string()];
+        let file = File::from_root(PathBuf::from("/backups/2024/05/23/dump.tar"), Path::new("/backups"), &formats).unwrap();
+        assert_eq!(file.get_date(), Utc.with_ymd_and_hms(2024, 5, 23, 0, 0, 0).unwrap());
+        assert_eq!(file.path, Path::new("/backups/2024/05/23"));
+        assert_eq!(file.root, Some(PathBuf::from("/backups")));
+
+        let formats = vec!["%Y/%m/%d".to_string()];
+        let file = File::from_root(PathBuf::from("/backups/2024/05/23"), Path::new("/backups/"), &formats).unwrap();
+        assert_eq!(file.path, Path::new("/backups/2024/05/23"));
+
+        assert!(File::from_root(PathBuf::from("/other/2024/05/23/dump.tar"), Path::new("/backups"), &formats).is_err());
+        assert!(File::from_root(PathBuf::from("/backups/2024/05/dump.tar"), Path::new("/backups"), &formats).is_err());
+
+        let formats = vec!["%Y/%m/%d/dump%%.tar".to_string()];
+        let file = File::from_root(PathBuf::from("/backups/2024/05/23/dump%.tar"), Path::new("/backups"), &formats).unwrap();
+        assert_eq!(file.path, Path::new("/backups/2024/05/23"));
+    }
+
+    #[test]
+    fn test_file_from_regex() {
+        let regex = capture::parse_regex(r"_(?<year>\d{4})-(?<month>\d{2})-(?<day>\d{2})_").unwrap();
+        let file = File::from_regex(PathBuf::from("/mnt/backup/pg_web01_2024-05-23_job4312.tar"), &regex).unwrap();
+        assert_eq!(file.get_date(), Utc.with_ymd_and_hms(2024, 5, 23, 0, 0, 0).unwrap());
+        assert!(File::from_regex(PathBuf::from("/mnt/2024-05-23_/pg_web01.tar"), &regex).is_err());
+    }
+
+    #[test]
+    fn test_file_from_detected() {
+        let file = File::from_detected(PathBuf::from("/mnt/2023-01-01/pg_20240523_030001.tar")).unwrap();
+        assert_eq!(file.get_date(), Utc.with_ymd_and_hms(2024, 5, 23, 3, 0, 1).unwrap());
+        assert!(File::from_detected(PathBuf::from("pg_2024-05-22_to_2024-05-23.tar")).is_err());
+    }
+
     #[test]
     fn test_file_from_fs() {
         let tmp_dir = tempdir::TempDir::new("example").unwrap();
@@ -169,9 +415,57 @@ mod test {
         drop(f);
         assert!(file_path.exists());
 
-        let fs_file = File::from_fs(file_path.to_str().unwrap().to_string()).unwrap();
+        let fs_file = File::from_fs(file_path.clone(), &TimeSource::Mtime, DirTime::Itself).unwrap();
 
-        let memory_file = File::new(file_path.to_str().unwrap().to_string(), Utc::now());
+        let memory_file = File::new(file_path.clone(), Utc::now());
         assert_eq!(fs_file.get_date().month(), memory_file.get_date().month());
     }
+
+    #[test]
+    fn test_file_from_fs_missing() {
+        let tmp_dir = tempdir::TempDir::new("example").unwrap();
+        let file_path = tmp_dir.path().join("missing");
+        assert!(File::from_fs(file_path.clone(), &TimeSource::Ctime, DirTime::Itself).is_err());
+    }
+
+    #[test]
+    fn test_file_from_archive() {
+        let tmp_dir = tempdir::TempDir::new("example").unwrap();
+        let file_path = tmp_dir.path().join("dump.sql.gz");
+        archive::utils::write_gzip(&file_path, 1716433201);
+        let file = File::from_archive(file_path.clone()).unwrap();
+        assert_eq!(file.get_date(), Utc.with_ymd_and_hms(2024, 5, 23, 3, 0, 1).unwrap());
+        assert!(File::from_archive(tmp_dir.path().join("missing")).is_err());
+    }
+
+    #[test]
+    fn test_file_from_sidecar() {
+        let tmp_dir = tempdir::TempDir::new("example").unwrap();
+        let file_path = tmp_dir.path().join("dump.tar");
+        let sidecar_path = tmp_dir.path().join("dump.tar.json");
+        fs::write(&sidecar_path, r#"{"created": "2024-05-23T03:00:01Z"}"#).unwrap();
+        let file = File::from_sidecar(file_path.clone(), "created").unwrap();
+        assert_eq!(file.get_date(), Utc.with_ymd_and_hms(2024, 5, 23, 3, 0, 1).unwrap());
+        assert_eq!(file.companions, vec![sidecar_path.clone()]);
+        assert!(File::from_sidecar(file_path.clone(), "missing").is_err());
+        fs::remove_file(&sidecar_path).unwrap();
+        assert!(File::from_sidecar(file_path.clone(), "created").is_err());
+    }
+
+    #[test]
+    fn test_file_from_non_utf8_path() {
+        let path = PathBuf::from(OsStr::from_bytes(b"/mnt/backup/caf\xe9_2024-05-23_03-00-01.tar"));
+        let expected = Utc.with_ymd_and_hms(2024, 5, 23, 3, 0, 1).unwrap();
+
+        let file = File::from_path_unanchored(path.clone(), &["%Y-%m-%d_%H-%M-%S".to_string()]).unwrap();
+        assert_eq!(file.get_date(), expected);
+        assert_eq!(file.path, path);
+
+        let regex = capture::parse_regex(r"(?<year>\d{4})-(?<month>\d{2})-(?<day>\d{2})_(?<hour>\d{2})").unwrap();
+        let file = File::from_regex(path.clone(), &regex).unwrap();
+        assert_eq!(file.get_date(), Utc.with_ymd_and_hms(2024, 5, 23, 3, 0, 0).unwrap());
+
+        let file = File::from_detected(path.clone()).unwrap();
+        assert_eq!(file.get_date(), expected);
+    }
 }
@@ -0,0 +1,91 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::suffix;
+
+pub const SUFFIXES: [&str; 2] = ["partial", "tmp"];
+pub const MARKER: &str = "inprogress";
+
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let split = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    let (amount, unit) = value.split_at(split);
+    let amount: u64 = amount.parse().map_err(|_| format!("Invalid duration: {}", value))?;
+    let seconds = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(format!("Invalid duration unit in {}, expected s, m, h or d", value)),
+    };
+    let seconds = amount.checked_mul(seconds).ok_or_else(|| format!("Duration too large: {}", value))?;
+    Ok(Duration::from_secs(seconds))
+}
+
+// A backup is still being written when it carries a `.partial` or `.tmp`
+// suffix, has a `<backup>.inprogress` marker next to it, or was modified
+// less than `min_age` ago. Markers themselves are never backups, nor are
+// the temporary files an interrupted run left behind.
+pub fn is_in_progress(path: &Path, min_age: Option<Duration>) -> bool {
+    let extension = path.extension().and_then(|extension| extension.to_str());
+    if extension.is_some_and(|extension| extension == MARKER || extension == suffix::TMP || SUFFIXES.contains(&extension)) {
+        return true;
+    }
+    if marker_path(path).exists() {
+        return true;
+    }
+    match (min_age, fs::metadata(path).and_then(|metadata| metadata.modified())) {
+        (Some(min_age), Ok(modified)) => {
+            SystemTime::now().duration_since(modified).unwrap_or_default() < min_age
+        }
+        _ => false,
+    }
+}
+
+fn marker_path(path: &Path) -> PathBuf {
+    suffix::append(path, MARKER)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("15m"), Ok(Duration::from_secs(900)));
+        assert_eq!(parse_duration("2h"), Ok(Duration::from_secs(7200)));
+        assert_eq!(parse_duration("1d"), Ok(Duration::from_secs(86400)));
+        assert_eq!(parse_duration("30"), Ok(Duration::from_secs(30)));
+        assert!(parse_duration("15 minutes").is_err());
+        assert!(parse_duration("m").is_err());
+        assert!(parse_duration("99999999999999999d").is_err());
+    }
+
+    #[test]
+    fn test_markers() {
+        let tmp_dir = TempDir::new("example").unwrap();
+        let done = tmp_dir.path().join("pg_2024-05-22.tar");
+        let marked = tmp_dir.path().join("pg_2024-05-23.tar");
+        fs::File::create(&done).unwrap();
+        fs::File::create(&marked).unwrap();
+        fs::File::create(tmp_dir.path().join("pg_2024-05-23.tar.inprogress")).unwrap();
+
+        assert!(!is_in_progress(&done, None));
+        assert!(is_in_progress(&marked, None));
+        assert!(is_in_progress(&tmp_dir.path().join("pg_2024-05-23.tar.inprogress"), None));
+        assert!(is_in_progress(Path::new("pg_2024-05-24.tar.partial"), None));
+        assert!(is_in_progress(Path::new("pg_2024-05-24.tar.tmp"), None));
+        assert!(is_in_progress(Path::new("pg_2024-05-24.tar.gz.rotation-tmp"), None));
+    }
+
+    #[test]
+    fn test_min_age() {
+        let tmp_dir = TempDir::new("example").unwrap();
+        let path = tmp_dir.path().join("pg_2024-05-23.tar");
+        fs::File::create(&path).unwrap();
+        assert!(is_in_progress(&path, Some(Duration::from_secs(900))));
+        assert!(!is_in_progress(&path, Some(Duration::ZERO)));
+        assert!(!is_in_progress(&path, None));
+    }
+}
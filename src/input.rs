@@ -0,0 +1,46 @@
+use std::ffi::OsString;
+use std::io::{self, BufRead};
+use std::os::unix::ffi::OsStringExt;
+use std::path::PathBuf;
+
+// Reads one path per line, or per NUL byte with `-0` so that names holding
+// newlines survive, as produced by `find -print0`.
+pub fn read_files<R: BufRead>(mut reader: R, null: bool) -> io::Result<Vec<PathBuf>> {
+    let delimiter = if null { b'\0' } else { b'\n' };
+    let mut files = vec![];
+    let mut entry = vec![];
+    while reader.read_until(delimiter, &mut entry)? > 0 {
+        if entry.last() == Some(&delimiter) {
+            entry.pop();
+        }
+        if !entry.is_empty() {
+            files.push(PathBuf::from(OsString::from_vec(entry.clone())));
+        }
+        entry.clear();
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_read_lines() {
+        let input = b"/backups/a.tar\n/backups/b.tar\n\n/backups/c.tar";
+        let files = read_files(&input[..], false).unwrap();
+        assert_eq!(files, vec![PathBuf::from("/backups/a.tar"), PathBuf::from("/backups/b.tar"), PathBuf::from("/backups/c.tar")]);
+    }
+
+    #[test]
+    fn test_read_null() {
+        let input = b"/backups/a\nb.tar\0/backups/caf\xe9.tar\0";
+        let files = read_files(&input[..], true).unwrap();
+        assert_eq!(files, vec![PathBuf::from("/backups/a\nb.tar"), PathBuf::from(OsString::from_vec(b"/backups/caf\xe9.tar".to_vec()))]);
+    }
+
+    #[test]
+    fn test_read_empty() {
+        assert!(read_files(&b""[..], false).unwrap().is_empty());
+    }
+}
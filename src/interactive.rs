@@ -0,0 +1,244 @@
+use chrono::{DateTime, Utc};
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use crate::action::{Action, ActionType};
+use crate::dated::Dated;
+use crate::display::display;
+use crate::file::File;
+use crate::layout::tier_name;
+use crate::plan::Plan;
+use crate::rotationplan::{RotationPlan, Tier};
+
+// Shows the kept backups and the planned actions, and returns the actions
+// the user approved. The end of the input rejects every action.
+pub fn review(
+    after: &RotationPlan<File>,
+    plan: Plan,
+    mut input: impl BufRead,
+    mut output: impl Write,
+    now: DateTime<Utc>,
+) -> io::Result<Plan> {
+    writeln!(output, "Kept backups:")?;
+    for tier in [Tier::Monthly, Tier::Weekly, Tier::Daily] {
+        writeln!(output, "  {}:", tier_name(tier))?;
+        for file in after.tier(tier) {
+            writeln!(output, "    {} ({})", display(&file.path), file.get_date().format("%Y-%m-%d %H:%M"))?;
+        }
+    }
+    if plan.is_empty() {
+        writeln!(output, "Nothing to do")?;
+        return Ok(plan);
+    }
+    let prerequisites = prerequisites(&plan);
+    let mut selected = vec![true; plan.len()];
+    loop {
+        writeln!(output, "Actions:")?;
+        for (i, action) in plan.iter().enumerate() {
+            let mark = if selected[i] { "x" } else { " " };
+            writeln!(output, "  [{}] {}. {}", mark, i + 1, describe(action, now))?;
+        }
+        write!(output, "Toggle actions by number, [a]pprove all, [r]eject all, or [y]es to take the selected actions: ")?;
+        output.flush()?;
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            writeln!(output)?;
+            return Ok(Plan::new());
+        }
+        match line.trim() {
+            "a" => return Ok(plan),
+            "r" => return Ok(Plan::new()),
+            "y" => return Ok(plan.into_iter().zip(selected).filter(|(_, selected)| *selected).map(|(action, _)| action).collect()),
+            choice => match parse_numbers(choice, plan.len()) {
+                Some(numbers) => {
+                    for number in numbers {
+                        let toggled = toggle(&mut selected, &prerequisites, number - 1);
+                        if !toggled.is_empty() {
+                            let toggled: Vec<String> = toggled.iter().map(|i| (i + 1).to_string()).collect();
+                            writeln!(output, "Also toggled {} with {}", toggled.join(", "), number)?;
+                        }
+                    }
+                }
+                None => writeln!(output, "Unknown choice: {}", choice)?,
+            },
+        }
+    }
+}
+
+// The earlier action whose result each action works on: a layout move
+// before a compression, or a compression before a link to the compressed
+// file.
+fn prerequisites(plan: &Plan) -> Vec<Option<usize>> {
+    let results: Vec<Option<PathBuf>> = plan.iter().map(|action| match &action.type_ {
+        ActionType::Move { dest } => Some(dest.clone()),
+        ActionType::Compress { codec } => Some(codec.compressed_path(&action.file.path)),
+        ActionType::Remove | ActionType::Link { .. } => None,
+    }).collect();
+    plan.iter().enumerate()
+        .map(|(i, action)| results[..i].iter().position(|result| result.as_ref() == Some(&action.file.path)))
+        .collect()
+}
+
+// Deselecting an action deselects the actions that depend on it, and
+// selecting one selects what it depends on, so that the selection never
+// refers to a file that will not exist. Returns the other actions toggled.
+fn toggle(selected: &mut [bool], prerequisites: &[Option<usize>], i: usize) -> Vec<usize> {
+    let before = selected.to_vec();
+    selected[i] = !selected[i];
+    match selected[i] {
+        true => {
+            let mut prerequisite = prerequisites[i];
+            while let Some(k) = prerequisite {
+                selected[k] = true;
+                prerequisite = prerequisites[k];
+            }
+        }
+        false => {
+            for j in i + 1..selected.len() {
+                if prerequisites[j].is_some_and(|k| !selected[k]) {
+                    selected[j] = false;
+                }
+            }
+        }
+    }
+    (0..selected.len()).filter(|j| *j != i && selected[*j] != before[*j]).collect()
+}
+
+fn parse_numbers(choice: &str, len: usize) -> Option<Vec<usize>> {
+    let numbers: Vec<usize> = choice.split([' ', ','])
+        .filter(|number| !number.is_empty())
+        .map(|number| number.parse().ok().filter(|number| (1..=len).contains(number)))
+        .collect::<Option<_>>()?;
+    (!numbers.is_empty()).then_some(numbers)
+}
+
+fn describe(action: &Action, now: DateTime<Utc>) -> String {
+    let path = display(&action.file.path);
+    let description = match &action.type_ {
+        ActionType::Remove => format!("remove {}", path),
+        ActionType::Move { dest } => format!("move {} -> {}", path, display(dest)),
+        ActionType::Compress { codec } => format!("compress {} -> {}", path, display(&codec.compressed_path(&action.file.path))),
+        ActionType::Link { dest } => format!("link {} -> {}", path, display(dest)),
+    };
+    let age = format_age(now - action.file.get_date());
+    let size = size(&action.file.path).map(format_size).unwrap_or_else(|| "missing".to_string());
+    match action.reason.is_empty() {
+        true => format!("{} ({}, {} old)", description, size, age),
+        false => format!("{} ({}, {} old): {}", description, size, age, action.reason),
+    }
+}
+
+// Directories count the size of everything below them.
+fn size(path: &Path) -> Option<u64> {
+    let metadata = fs::symlink_metadata(path).ok()?;
+    if !metadata.is_dir() {
+        return Some(metadata.len());
+    }
+    let entries = fs::read_dir(path).ok()?;
+    Some(entries.filter_map(|entry| entry.ok()).filter_map(|entry| size(&entry.path())).sum())
+}
+
+fn format_size(size: u64) -> String {
+    let units = ["KiB", "MiB", "GiB", "TiB"];
+    if size < 1024 {
+        return format!("{} B", size);
+    }
+    let mut value = size as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < units.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, units[unit])
+}
+
+fn format_age(age: chrono::Duration) -> String {
+    match age.num_days() {
+        0 => format!("{}h", age.num_hours().max(0)),
+        days => format!("{}d", days),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::compress::Codec;
+    use crate::file::utils::{test_file, test_file_at};
+    use chrono::TimeZone;
+    use tempdir::TempDir;
+
+    fn review_with(input: &str) -> (Plan, String) {
+        let after = RotationPlan { daily: vec![test_file("c.tar", 3)].into(), ..RotationPlan::new() };
+        let plan = vec![
+            Action::new(ActionType::Remove, test_file("a.tar", 1), "not kept by the rotation"),
+            Action::new(ActionType::Remove, test_file("b.tar", 2), "not kept by the rotation"),
+        ];
+        let now = Utc.with_ymd_and_hms(2024, 1, 10, 0, 0, 0).unwrap();
+        let mut output = vec![];
+        let approved = review(&after, plan, input.as_bytes(), &mut output, now).unwrap();
+        (approved, String::from_utf8(output).unwrap())
+    }
+
+    fn paths(plan: &Plan) -> Vec<&str> {
+        plan.iter().map(|action| action.file.path.to_str().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_review() {
+        let (approved, output) = review_with("a\n");
+        assert_eq!(paths(&approved), vec!["a.tar", "b.tar"]);
+        assert!(output.contains("  daily:\n    c.tar (2024-01-04 00:00)\n"));
+        assert!(output.contains("  [x] 1. remove a.tar (missing, 8d old): not kept by the rotation\n"));
+
+        assert!(review_with("r\n").0.is_empty());
+        assert!(review_with("").0.is_empty());
+        assert_eq!(paths(&review_with("1\ny\n").0), vec!["b.tar"]);
+        assert_eq!(paths(&review_with("1,2\n2\ny\n").0), vec!["b.tar"]);
+
+        let (approved, output) = review_with("3\ny\n");
+        assert_eq!(approved.len(), 2);
+        assert!(output.contains("Unknown choice: 3\n"));
+    }
+
+    #[test]
+    fn test_dependencies() {
+        let after = RotationPlan::new();
+        let plan = vec![
+            Action::new(ActionType::Move { dest: PathBuf::from("monthly/a.tar") }, test_file("a.tar", 1), ""),
+            Action::new(ActionType::Compress { codec: Codec::Gzip }, test_file("monthly/a.tar", 1), ""),
+            Action::new(ActionType::Link { dest: PathBuf::from("weekly/a.tar.gz") }, test_file("monthly/a.tar.gz", 1), ""),
+            Action::new(ActionType::Remove, test_file("b.tar", 2), ""),
+        ];
+        assert_eq!(prerequisites(&plan), vec![None, Some(0), Some(1), None]);
+
+        let now = Utc.with_ymd_and_hms(2024, 1, 10, 0, 0, 0).unwrap();
+        let mut output = vec![];
+        let approved = review(&after, plan, "1\ny\n".as_bytes(), &mut output, now).unwrap();
+        assert_eq!(paths(&approved), vec!["b.tar"]);
+        assert!(String::from_utf8(output).unwrap().contains("Also toggled 2, 3 with 1\n"));
+
+        let mut selected = vec![false, false, false, true];
+        assert_eq!(toggle(&mut selected, &[None, Some(0), Some(1), None], 2), vec![0, 1]);
+        assert_eq!(selected, vec![true; 4]);
+    }
+
+    #[test]
+    fn test_sizes() {
+        let tmp_dir = TempDir::new("example").unwrap();
+        let dir = tmp_dir.path().join("pg_2024-05-23");
+        fs::create_dir_all(dir.join("base")).unwrap();
+        fs::write(dir.join("base/1"), vec![0; 1000]).unwrap();
+        fs::write(dir.join("PG_VERSION"), vec![0; 48]).unwrap();
+        assert_eq!(size(&dir), Some(1048));
+        assert_eq!(size(&tmp_dir.path().join("missing")), None);
+
+        let now = Utc.with_ymd_and_hms(2024, 1, 2, 6, 0, 0).unwrap();
+        let action = Action::new(ActionType::Remove, test_file_at(&dir, 1), "");
+        assert_eq!(describe(&action, now), format!("remove {} (1.0 KiB, 6h old)", display(&dir)));
+
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(3 * 1024 * 1024 * 1024), "3.0 GiB");
+    }
+}
@@ -0,0 +1,223 @@
+use serde_json::{json, Value};
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+
+use crate::action::{Action, ActionType};
+use crate::compress::decompress_to;
+use crate::display::display;
+use crate::relocate::move_path;
+
+// One JSON object per line: the run first, then each action once it is done,
+// so that an interrupted run can still be restored up to where it stopped.
+pub struct Journal {
+    file: fs::File,
+}
+
+impl Journal {
+    pub fn create(dir: &Path, run_id: &str) -> io::Result<Journal> {
+        fs::create_dir_all(dir)?;
+        let file = fs::OpenOptions::new().create_new(true).append(true).open(path(dir, run_id))?;
+        let mut journal = Journal { file };
+        journal.write(json!({ "run": run_id }))?;
+        Ok(journal)
+    }
+
+    pub fn record(&mut self, action: &Action) -> io::Result<()> {
+        let companions: Vec<Value> = action.file.companions.iter()
+            .map(|companion| json!({
+                "path": to_value(companion),
+                "dest": action.companion_dest(companion).as_deref().map(to_value),
+            }))
+            .collect();
+        let (name, dest) = match &action.type_ {
+            ActionType::Remove => ("remove", None),
+            ActionType::Move { dest } => ("move", Some(to_value(dest))),
+            ActionType::Compress { codec } => ("compress", Some(to_value(&codec.compressed_path(&action.file.path)))),
+            ActionType::Link { dest } => ("link", Some(to_value(dest))),
+        };
+        self.write(json!({
+            "action": name,
+            "path": to_value(&action.file.path),
+            "dest": dest,
+            "companions": companions,
+        }))
+    }
+
+    fn write(&mut self, entry: Value) -> io::Result<()> {
+        writeln!(self.file, "{}", entry)?;
+        self.file.sync_data()
+    }
+}
+
+pub fn path(dir: &Path, run_id: &str) -> PathBuf {
+    dir.join(format!("{}.jsonl", run_id))
+}
+
+pub fn default_dir() -> Result<PathBuf, String> {
+    state_dir(env::var_os("XDG_STATE_HOME"), env::var_os("HOME"))
+}
+
+// Relative paths are ignored, so that a missing HOME never leaves journals in
+// whatever directory the run was started from.
+fn state_dir(xdg_state_home: Option<OsString>, home: Option<OsString>) -> Result<PathBuf, String> {
+    let absolute = |dir: Option<OsString>| dir.map(PathBuf::from).filter(|dir| dir.is_absolute());
+    let state = absolute(xdg_state_home)
+        .or_else(|| absolute(home).map(|home| home.join(".local/state")))
+        .ok_or("Neither XDG_STATE_HOME nor HOME is set to an absolute path, pass --journal-dir")?;
+    Ok(state.join("backup-rotation"))
+}
+
+// Moves everything a run put in the trash, the archive or a tier directory
+// back, decompresses what it compressed and removes the links it made,
+// newest action first. Removed files cannot be brought back and are only
+// reported.
+pub fn restore(dir: &Path, run_id: &str) -> io::Result<()> {
+    let journal = fs::File::open(path(dir, run_id))?;
+    let mut moves = vec![];
+    for line in io::BufReader::new(journal).lines() {
+        let entry: Value = serde_json::from_str(&line?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let action = match entry.get("action").and_then(|action| action.as_str()) {
+            Some(action) => action.to_string(),
+            None => continue,
+        };
+        moves.push((action.clone(), entry["path"].clone(), entry["dest"].clone()));
+        // Companions of a compressed backup were only renamed, and those
+        // without a destination were left where they were.
+        let companion_action = if action == "compress" { "move".to_string() } else { action.clone() };
+        for companion in entry["companions"].as_array().into_iter().flatten() {
+            if action != "remove" && companion["dest"].is_null() {
+                continue;
+            }
+            moves.push((companion_action.clone(), companion["path"].clone(), companion["dest"].clone()));
+        }
+    }
+    for (action, path, dest) in moves.iter().rev() {
+        let path = from_value(path).ok_or_else(|| invalid_entry(path))?;
+        match (action.as_str(), from_value(dest)) {
+            ("move", Some(dest)) => {
+                println!("Restoring file: {} -> {}", display(&dest), display(&path));
+                if let Err(e) = move_path(&dest, &path) {
+                    println!("Failed to restore {}: {}", display(&path), e);
+                }
+            }
+            ("compress", Some(dest)) => {
+                println!("Decompressing file: {} -> {}", display(&dest), display(&path));
+                if let Err(e) = decompress_to(&dest, &path) {
+                    println!("Failed to restore {}: {}", display(&path), e);
+                }
+            }
+            ("link", Some(dest)) => {
+                println!("Removing link: {}", display(&dest));
+                if let Err(e) = fs::remove_file(&dest) {
+                    println!("Failed to remove {}: {}", display(&dest), e);
+                }
+            }
+            _ => println!("Cannot restore removed file: {}", display(&path)),
+        }
+    }
+    Ok(())
+}
+
+// Paths are made absolute so that a run can be restored from any directory.
+// Paths that are not valid UTF-8 are kept as an array of bytes.
+pub fn to_value(path: &Path) -> Value {
+    let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    match path.to_str() {
+        Some(path) => json!(path),
+        None => json!(path.as_os_str().as_bytes()),
+    }
+}
+
+pub fn from_value(value: &Value) -> Option<PathBuf> {
+    match value {
+        Value::String(path) => Some(PathBuf::from(path)),
+        Value::Array(bytes) => {
+            let bytes: Option<Vec<u8>> = bytes.iter().map(|byte| byte.as_u64().map(|byte| byte as u8)).collect();
+            Some(PathBuf::from(OsString::from_vec(bytes?)))
+        }
+        _ => None,
+    }
+}
+
+fn invalid_entry(value: &Value) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("invalid path in journal: {}", value))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::compress::{compress, Codec};
+    use crate::file::utils::test_file_at;
+    use std::ffi::OsStr;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_state_dir() {
+        assert_eq!(state_dir(Some("/state".into()), Some("/home/a".into())), Ok(PathBuf::from("/state/backup-rotation")));
+        assert_eq!(state_dir(Some("".into()), Some("/home/a".into())), Ok(PathBuf::from("/home/a/.local/state/backup-rotation")));
+        assert_eq!(state_dir(None, Some("/home/a".into())), Ok(PathBuf::from("/home/a/.local/state/backup-rotation")));
+        assert!(state_dir(None, None).is_err());
+        assert!(state_dir(Some("state".into()), Some("".into())).is_err());
+    }
+
+    #[test]
+    fn test_path_values() {
+        let path = Path::new(OsStr::from_bytes(b"/backups/caf\xe9.tar"));
+        assert_eq!(from_value(&to_value(path)).unwrap(), path);
+        assert_eq!(to_value(Path::new("/backups/a.tar")), json!("/backups/a.tar"));
+    }
+
+    #[test]
+    fn test_restore() {
+        let tmp_dir = TempDir::new("example").unwrap();
+        let journal_dir = tmp_dir.path().join("journal");
+        let original = tmp_dir.path().join("a.tar");
+        let checksum = tmp_dir.path().join("a.tar.sha256");
+        let removed = tmp_dir.path().join("b.tar");
+        let dest = tmp_dir.path().join("archive/a.tar");
+        fs::create_dir(tmp_dir.path().join("archive")).unwrap();
+        fs::write(&dest, "dump").unwrap();
+        fs::write(tmp_dir.path().join("archive/a.tar.sha256"), "sum").unwrap();
+
+        let mut file = test_file_at(&original, 1);
+        file.companions.push(checksum.clone());
+        let mut journal = Journal::create(&journal_dir, "20240523T030001Z").unwrap();
+        journal.record(&Action::new(ActionType::Move { dest: dest.clone() }, file, "")).unwrap();
+        journal.record(&Action::new(ActionType::Remove, test_file_at(&removed, 2), "")).unwrap();
+        assert!(Journal::create(&journal_dir, "20240523T030001Z").is_err());
+
+        restore(&journal_dir, "20240523T030001Z").unwrap();
+        assert_eq!(fs::read_to_string(&original).unwrap(), "dump");
+        assert_eq!(fs::read_to_string(&checksum).unwrap(), "sum");
+        assert!(!dest.exists());
+        assert!(restore(&journal_dir, "20240524T030001Z").is_err());
+    }
+
+    #[test]
+    fn test_restore_compressed() {
+        let tmp_dir = TempDir::new("example").unwrap();
+        let journal_dir = tmp_dir.path().join("journal");
+        let original = tmp_dir.path().join("a.tar");
+        let checksum = tmp_dir.path().join("a.tar.sha256");
+        fs::write(&original, "dump").unwrap();
+        fs::write(&checksum, "sum").unwrap();
+
+        let mut file = test_file_at(&original, 1);
+        file.companions.push(checksum.clone());
+        file.companions.push(tmp_dir.path().join("a.log"));
+        let action = Action::new(ActionType::Compress { codec: Codec::Zstd }, file, "");
+        let compressed = compress(&original, Codec::Zstd).unwrap();
+        fs::rename(&checksum, tmp_dir.path().join("a.tar.zst.sha256")).unwrap();
+        let mut journal = Journal::create(&journal_dir, "20240523T030001Z").unwrap();
+        journal.record(&action).unwrap();
+
+        restore(&journal_dir, "20240523T030001Z").unwrap();
+        assert_eq!(fs::read_to_string(&original).unwrap(), "dump");
+        assert_eq!(fs::read_to_string(&checksum).unwrap(), "sum");
+        assert!(!compressed.exists());
+    }
+}
@@ -0,0 +1,233 @@
+use chrono::Utc;
+use clap::ValueEnum;
+use std::collections::{BTreeSet, HashSet};
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use crate::action::{Action, ActionType};
+use crate::file::File;
+use crate::plan::Plan;
+use crate::rotationplan::{RotationPlan, Tier};
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Layout {
+    /// Hard link each kept backup into the directory of each of its tiers
+    Hardlink,
+    /// Move each kept backup into the directory of its longest tier
+    Move,
+}
+
+const TIERS: [Tier; 3] = [Tier::Monthly, Tier::Weekly, Tier::Daily];
+
+pub fn tier_name(tier: Tier) -> &'static str {
+    match tier {
+        Tier::Daily => "daily",
+        Tier::Weekly => "weekly",
+        Tier::Monthly => "monthly",
+    }
+}
+
+fn is_tier_name(path: &Path) -> bool {
+    let name = path.file_name().and_then(|name| name.to_str());
+    TIERS.iter().any(|tier| name == Some(tier_name(*tier)))
+}
+
+pub fn is_tier_dir(path: &Path) -> bool {
+    is_tier_name(path) && path.is_dir()
+}
+
+pub fn is_in_tier_dir(path: &Path) -> bool {
+    path.parent().is_some_and(is_tier_name)
+}
+
+// The directory holding the tier directories, also for a backup already
+// moved into one of them.
+pub fn base_dir(path: &Path) -> &Path {
+    let parent = path.parent().unwrap_or(Path::new(""));
+    match is_tier_name(parent) {
+        true => parent.parent().unwrap_or(Path::new("")),
+        false => parent,
+    }
+}
+
+// With `move`, the backups in the tier directories are the only copies and
+// are rotated like the others. With `hardlink`, they are links to backups
+// that are already listed, so they are left out.
+pub fn expand(files: Vec<PathBuf>, layout: Layout) -> Vec<PathBuf> {
+    let mut expanded = vec![];
+    for file in files {
+        match layout {
+            Layout::Move if is_tier_dir(&file) => {
+                let mut entries: Vec<PathBuf> = fs::read_dir(&file)
+                    .map(|entries| entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()).collect())
+                    .unwrap_or_default();
+                entries.sort();
+                expanded.extend(entries);
+            }
+            Layout::Hardlink if is_tier_dir(&file) || is_in_tier_dir(&file) => {}
+            _ => expanded.push(file),
+        }
+    }
+    expanded
+}
+
+fn tier_path(file: &File, tier: Tier) -> PathBuf {
+    base_dir(&file.path).join(tier_name(tier)).join(file.path.file_name().unwrap_or_default())
+}
+
+// Moves each kept backup into its longest tier, and returns the rotation with
+// the paths the backups will have once the plan has run.
+pub fn move_plan(after: &RotationPlan<File>) -> (Plan, RotationPlan<File>) {
+    let mut plan = Plan::new();
+    for tier in TIERS {
+        for file in after.tier(tier) {
+            if plan.iter().any(|action: &Action| action.file == *file) {
+                continue;
+            }
+            let dest = tier_path(file, tier);
+            if dest != file.path {
+                let reason = format!("kept in the {} tier", tier_name(tier));
+                plan.push(Action::new(ActionType::Move { dest }, file.clone(), &reason));
+            }
+        }
+    }
+    let moved = after.map(|file| {
+        let mut file = file.clone();
+        let action = plan.iter().find(|action| action.file == file);
+        if let Some(ActionType::Move { dest }) = action.map(|action| &action.type_) {
+            file.path = dest.clone();
+        }
+        file
+    });
+    (plan, moved)
+}
+
+// Links each kept backup into each of its tiers, and removes the links left
+// from earlier runs for backups that left a tier. Only hard links to one of
+// the `listed` backups are removed, so that anything else in the tier
+// directories, including directories, is left alone. Directories cannot be
+// hard linked and are left out.
+pub fn link_plan(after: &RotationPlan<File>, listed: &[File]) -> Plan {
+    let mut plan = Plan::new();
+    let mut wanted = HashSet::new();
+    let mut bases = BTreeSet::new();
+    for tier in TIERS {
+        for file in after.tier(tier) {
+            if file.path.is_dir() {
+                continue;
+            }
+            let dest = tier_path(file, tier);
+            bases.insert(base_dir(&file.path).to_path_buf());
+            wanted.insert(dest.clone());
+            if !same_file(&file.path, &dest) {
+                let reason = format!("kept in the {} tier", tier_name(tier));
+                plan.push(Action::new(ActionType::Link { dest }, file.clone(), &reason));
+            }
+        }
+    }
+    let inodes: HashSet<(u64, u64)> = listed.iter()
+        .filter_map(|file| fs::symlink_metadata(&file.path).ok())
+        .map(|metadata| (metadata.dev(), metadata.ino()))
+        .collect();
+    for base in bases {
+        for tier in TIERS {
+            let entries = match fs::read_dir(base.join(tier_name(tier))) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+            let mut stale: Vec<PathBuf> = entries.filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| !wanted.contains(path) && is_link_to(path, &inodes))
+                .collect();
+            stale.sort();
+            for path in stale {
+                let reason = format!("no longer in the {} tier", tier_name(tier));
+                plan.push(Action::new(ActionType::Remove, File::new(path, Utc::now()), &reason));
+            }
+        }
+    }
+    plan
+}
+
+fn is_link_to(path: &Path, inodes: &HashSet<(u64, u64)>) -> bool {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata.is_file() && metadata.nlink() > 1 && inodes.contains(&(metadata.dev(), metadata.ino())),
+        Err(_) => false,
+    }
+}
+
+pub fn same_file(a: &Path, b: &Path) -> bool {
+    match (fs::metadata(a), fs::metadata(b)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::file::utils::test_file_at;
+    use tempdir::TempDir;
+
+    fn rotation(daily: Vec<File>, weekly: Vec<File>, monthly: Vec<File>) -> RotationPlan<File> {
+        RotationPlan { daily: daily.into(), weekly: weekly.into(), monthly: monthly.into() }
+    }
+
+    #[test]
+    fn test_base_dir() {
+        assert_eq!(base_dir(Path::new("/backups/a.tar")), Path::new("/backups"));
+        assert_eq!(base_dir(Path::new("/backups/weekly/a.tar")), Path::new("/backups"));
+    }
+
+    #[test]
+    fn test_expand() {
+        let tmp_dir = TempDir::new("example").unwrap();
+        fs::create_dir(tmp_dir.path().join("weekly")).unwrap();
+        fs::File::create(tmp_dir.path().join("weekly/b.tar")).unwrap();
+        let files = vec![tmp_dir.path().join("a.tar"), tmp_dir.path().join("weekly")];
+        assert_eq!(expand(files.clone(), Layout::Move), vec![tmp_dir.path().join("a.tar"), tmp_dir.path().join("weekly/b.tar")]);
+        assert_eq!(expand(files, Layout::Hardlink), vec![tmp_dir.path().join("a.tar")]);
+        assert!(expand(vec![tmp_dir.path().join("daily/c.tar")], Layout::Hardlink).is_empty());
+    }
+
+    #[test]
+    fn test_move_plan() {
+        let a = test_file_at(Path::new("/backups/a.tar"), 1);
+        let b = test_file_at(Path::new("/backups/weekly/b.tar"), 8);
+        let c = test_file_at(Path::new("/backups/daily/c.tar"), 9);
+        let (plan, moved) = move_plan(&rotation(vec![c.clone()], vec![a.clone(), b.clone()], vec![a.clone()]));
+        assert_eq!(plan.len(), 1);
+        assert_eq!(plan[0].type_, ActionType::Move { dest: PathBuf::from("/backups/monthly/a.tar") });
+        assert_eq!(moved.weekly[0].path, Path::new("/backups/monthly/a.tar"));
+        assert_eq!(moved.weekly[1].path, Path::new("/backups/weekly/b.tar"));
+        assert_eq!(moved.daily[0].path, Path::new("/backups/daily/c.tar"));
+    }
+
+    #[test]
+    fn test_link_plan() {
+        let tmp_dir = TempDir::new("example").unwrap();
+        let a = tmp_dir.path().join("a.tar");
+        let b = tmp_dir.path().join("b.tar");
+        let old = tmp_dir.path().join("old.tar");
+        fs::File::create(&a).unwrap();
+        fs::File::create(&b).unwrap();
+        fs::File::create(&old).unwrap();
+        fs::create_dir_all(tmp_dir.path().join("daily/otherhost")).unwrap();
+        fs::hard_link(&b, tmp_dir.path().join("daily/b.tar")).unwrap();
+        fs::hard_link(&old, tmp_dir.path().join("daily/old.tar")).unwrap();
+        fs::File::create(tmp_dir.path().join("daily/notes.txt")).unwrap();
+        fs::File::create(tmp_dir.path().join("daily/moved.tar")).unwrap();
+
+        let after = rotation(vec![test_file_at(&b, 2)], vec![test_file_at(&a, 1)], vec![test_file_at(&a, 1)]);
+        let listed = vec![test_file_at(&a, 1), test_file_at(&b, 2), test_file_at(&old, 0)];
+        let plan = link_plan(&after, &listed);
+        let types: Vec<&ActionType> = plan.iter().map(|action| &action.type_).collect();
+        assert_eq!(types, vec![
+            &ActionType::Link { dest: tmp_dir.path().join("monthly/a.tar") },
+            &ActionType::Link { dest: tmp_dir.path().join("weekly/a.tar") },
+            &ActionType::Remove,
+        ]);
+        assert_eq!(plan[2].file.path, tmp_dir.path().join("daily/old.tar"));
+    }
+}
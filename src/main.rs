@@ -1,72 +1,348 @@
 mod rotationplan;
 mod rotator;
+mod sidecar;
 mod dated;
 mod file;
+mod input;
 mod action;
 mod execute;
 mod plan;
 mod args;
+mod archive;
+mod capture;
+mod detect;
+mod display;
+mod precision;
+mod timesource;
+mod symlink;
+mod inprogress;
+mod companion;
+mod relocate;
+mod trash;
+mod journal;
+mod compress;
+mod layout;
+mod planfile;
+mod interactive;
+mod parallel;
+mod throttle;
+mod suffix;
 
 use file::File;
-use plan::create_plan;
+use dated::Dated;
+use plan::{compress_plan, create_plan, Disposal, Plan};
+use compress::{Codec, Compression};
+use layout::Layout;
+use rotationplan::RotationPlan;
 use execute::execute_plan;
-use args::Args;
+use action::ActionType;
+use args::{Args, Cli, Command, MismatchPolicy, Removal};
+use journal::Journal;
+use std::collections::HashSet;
+use std::fs;
+use std::io::IsTerminal;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use display::display;
+use timesource::{DirTime, TimeSource};
+use symlink::SymlinkPolicy;
+use throttle::{Limiter, Throttle};
 use clap::Parser;
+use chrono::Utc;
 
-fn files_after_rotation(files_before: &Vec<File>) -> Vec<File> {
+fn rotate(files_before: &[File]) -> RotationPlan<File> {
     let mut rotator = rotator::Rotator::<File>::new(3, 2, 1);
-    for backup in files_before {
-        rotator.add_backup(backup.clone());
+    // Backups come in the order they were listed, which no longer follows
+    // their dates once some of them sit in tier directories.
+    let mut sorted = files_before.to_vec();
+    sorted.sort_by_key(|backup| backup.get_date());
+    for backup in sorted {
+        rotator.add_backup(backup);
     }
-    rotator.get_backups().as_vec()
+    rotator.get_backups()
 }
 
+fn plan_rotation(
+    before: &Vec<File>,
+    after: &RotationPlan<File>,
+    disposal: &Disposal,
+    compression: Option<&Compression>,
+    layout: Option<Layout>,
+) -> (Plan, RotationPlan<File>) {
+    let mut plan = create_plan(before, &after.as_vec(), disposal);
+    let mut after = after.clone();
+    if layout == Some(Layout::Move) {
+        let (moves, moved) = layout::move_plan(&after);
+        plan.extend(moves);
+        after = moved;
+    }
+    if let Some(compression) = compression {
+        let compressions = compress_plan(&after, compression);
+        // Links are made to the compressed backups, once they exist.
+        after = after.map(|file| {
+            let mut file = file.clone();
+            if compressions.iter().any(|action| action.file == file) {
+                file.path = compression.codec.compressed_path(&file.path);
+            }
+            file
+        });
+        plan.extend(compressions);
+    }
+    if layout == Some(Layout::Hardlink) {
+        plan.extend(layout::link_plan(&after, before));
+    }
+    (plan, after)
+}
 
-fn apply_rotation(before: &Vec<File>, after: &Vec<File>) {
-    let plan = create_plan(before, after);
-    execute_plan(plan);
+fn get_backup(file: &Path, args: &Args) -> Result<File, String> {
+    let path = file.to_path_buf();
+    let parse_error = |_| format!("Failed to parse path: {}", display(file));
+    if let Some(regex) = &args.regex {
+        return File::from_regex(path, regex).map_err(parse_error);
+    }
+    if args.detect_date {
+        return File::from_detected(path);
+    }
+    if let Some(root) = &args.root {
+        return File::from_root(path, root, &args.format).map_err(parse_error);
+    }
+    if !args.format.is_empty() {
+        return match args.unanchored {
+            true => File::from_path_unanchored(path, &args.format).map_err(parse_error),
+            false => File::from_path(path, &args.format).map_err(parse_error),
+        };
+    }
+    match &args.time_source {
+        TimeSource::Archive => File::from_archive(path),
+        TimeSource::Sidecar(key) => File::from_sidecar(path, key),
+        source => File::from_fs(path, source, args.dir_time),
+    }
 }
 
 fn get_backups(args: Args) -> Vec<File> {
-    match args.format {
-        Some(format) => {
-            let mut files = vec![];
-            for file in &args.files {
-                let file = File::from_path(file.clone(), &format);
-                files.push(file.unwrap());
+    let mut files = vec![];
+    let mut seen = HashSet::new();
+    let mut inodes = HashSet::new();
+    let listed: HashSet<&Path> = args.files.iter().map(PathBuf::as_path).collect();
+    let companions = companion::companions_of(&args.files, &args.companion);
+    for file in &args.files {
+        if matches!(args.time_source, TimeSource::Sidecar(_)) && sidecar::is_sidecar_of(file, &listed) {
+            continue;
+        }
+        if trash::is_trash(file) {
+            continue;
+        }
+        if throttle::is_marker(file) {
+            continue;
+        }
+        if companions.contains(file) {
+            continue;
+        }
+        let target;
+        let file = match (args.symlinks, symlink::is_symlink(file)) {
+            (SymlinkPolicy::Skip, true) => {
+                println!("Skipping symlink: {}", display(file));
+                continue;
             }
-            files
+            (SymlinkPolicy::Target, true) => match symlink::resolve(file) {
+                Ok(path) => {
+                    target = path;
+                    &target
+                }
+                Err(e) => panic!("Failed to read symlink {}: {}", display(file), e),
+            },
+            _ => file,
+        };
+        if inprogress::is_in_progress(file, args.min_age) {
+            println!("Skipping in-progress file: {}", display(file));
+            continue;
         }
-        None => {
-            let mut files = vec![];
-            for file in &args.files {
-                let file = File::from_fs(file.clone());
-                files.push(file.unwrap());
+        match get_backup(file, &args) {
+            Ok(mut backup) => {
+                for path in companion::find(&backup.path, &args.companion) {
+                    if !backup.companions.contains(&path) {
+                        backup.companions.push(path);
+                    }
+                }
+                // With the hardlink layout, links to the same backup are counted once.
+                if args.layout == Some(Layout::Hardlink) {
+                    let inode = fs::symlink_metadata(&backup.path).ok().map(|metadata| (metadata.dev(), metadata.ino()));
+                    if inode.is_some_and(|inode| !inodes.insert(inode)) {
+                        continue;
+                    }
+                }
+                // Several files under --root can belong to the same date directory,
+                // and a symlink can resolve to a file that is also listed.
+                if seen.insert(backup.path.clone()) {
+                    files.push(backup);
+                }
             }
-            files
+            Err(e) => match args.on_mismatch {
+                MismatchPolicy::Error => panic!("{}", e),
+                MismatchPolicy::Skip => println!("Skipping file: {}", e),
+            },
         }
     }
+    files
+}
+
+fn journal_dir(journal_dir: Option<PathBuf>) -> PathBuf {
+    journal_dir.map_or_else(journal::default_dir, Ok).unwrap_or_else(|e| panic!("{}", e))
+}
+
+// A run with nothing to do writes no journal. Without a journal directory,
+// removals still go ahead, since the journal could not bring them back anyway.
+fn create_journal(journal_dir: Option<PathBuf>, run_id: &str, plan: &Plan) -> Option<Journal> {
+    if plan.is_empty() {
+        return None;
+    }
+    let journal_dir = match journal_dir.map_or_else(journal::default_dir, Ok) {
+        Ok(journal_dir) => journal_dir,
+        Err(e) if plan.iter().all(|action| action.type_ == ActionType::Remove) => {
+            println!("Warning: {}, running without a journal", e);
+            return None;
+        }
+        Err(e) => panic!("{}", e),
+    };
+    let journal = Journal::create(&journal_dir, run_id)
+        .unwrap_or_else(|e| panic!("Failed to create the journal in {}: {}", display(&journal_dir), e));
+    println!("Run {}", run_id);
+    Some(journal)
+}
+
+// Removals interrupted in an earlier run are finished first, at the same pace.
+fn resume_removals(paths: &[PathBuf], root: Option<&Path>, throttle: Option<Throttle>) {
+    throttle::resume(paths, root, &mut Limiter::new(throttle.unwrap_or_default()))
+        .unwrap_or_else(|e| panic!("Failed to resume an interrupted removal: {}", e));
+}
+
+fn apply(plan_path: &Path, journal_dir: Option<PathBuf>, removal: &Removal) {
+    let throttle = Throttle::from_args(removal);
+    let plan = planfile::read(plan_path).unwrap_or_else(|e| panic!("Refusing to apply the plan: {}", e));
+    let paths: Vec<PathBuf> = plan.iter().map(|action| action.file.path.clone()).collect();
+    let root = plan.iter().find_map(|action| action.file.root.clone());
+    resume_removals(&paths, root.as_deref(), throttle);
+    let mut journal = create_journal(journal_dir, &trash::run_id(Utc::now()), &plan);
+    execute_plan(plan, journal.as_mut(), removal.jobs as usize, throttle);
 }
 
 fn main() {
-    let args = Args::parse();
-    
+    let cli = Cli::parse();
+    match cli.command {
+        Some(Command::Restore { run, journal_dir }) => {
+            journal::restore(&self::journal_dir(journal_dir), &run)
+                .unwrap_or_else(|e| panic!("Failed to restore run {}: {}", run, e));
+        }
+        Some(Command::Plan { output, args }) => run(*args, Some(&output)),
+        Some(Command::Apply { plan, journal_dir, removal }) => apply(&plan, journal_dir, &removal),
+        None => run(cli.args, None),
+    }
+}
+
+// With an output, the plan is written there and nothing else is touched.
+fn run(mut args: Args, output: Option<&Path>) {
+    // Answers must come from a person, never from a pipe.
+    if args.interactive && !std::io::stdin().is_terminal() {
+        panic!("--interactive needs a terminal on stdin");
+    }
+
+    // Archive and sidecar times are read once for the backup as a whole.
+    if args.dir_time != DirTime::Itself && matches!(args.time_source, TimeSource::Archive | TimeSource::Sidecar(_)) {
+        panic!("--dir-time cannot be used with --time-source {}", args.time_source);
+    }
+
+    match (args.compress, &args.time_source) {
+        (Some(_), TimeSource::Ctime | TimeSource::Atime | TimeSource::Btime) => {
+            panic!("--compress cannot be used with --time-source {}, which a compressed copy does not keep", args.time_source);
+        }
+        (Some(Codec::Zstd), TimeSource::Archive) => {
+            panic!("--compress zstd cannot be used with --time-source archive, since zstd files record no time");
+        }
+        _ => {}
+    }
+
+    if args.stdin {
+        args.files = input::read_files(std::io::stdin().lock(), args.null)
+            .unwrap_or_else(|e| panic!("Failed to read files from stdin: {}", e));
+    }
+
+    if let Some(layout) = args.layout {
+        args.files = layout::expand(args.files, layout);
+    }
+
     if args.files.is_empty() {
         println!("No files to rotate");
         return;
     }
 
 
+    for format in &args.format {
+        let precision = precision::precision(format).unwrap();
+        println!("Using format {} with {} precision", format, precision);
+    }
+
+    let links: Vec<PathBuf> = args.files.iter().filter(|file| symlink::is_symlink(file)).cloned().collect();
+    let retarget_links = args.retarget_links;
+    let run_id = trash::run_id(Utc::now());
+    let trash_grace = args.trash.then_some(args.trash_grace);
+    let disposal = match (&args.archive_dir, trash_grace) {
+        (Some(dir), _) => Disposal::Archive(dir.clone()),
+        (None, Some(_)) => Disposal::Trash(run_id.clone()),
+        (None, None) => Disposal::Remove,
+    };
+    let journal_dir = args.journal_dir.clone();
+    let layout = args.layout;
+    let compression = args.compress.map(|codec| Compression { codec, tiers: args.compress_tiers.clone() });
+    let interactive = args.interactive;
+    let jobs = args.removal.jobs as usize;
+    let throttle = Throttle::from_args(&args.removal);
+    let root = args.root.clone();
+    // Interrupted removals are left out of the rotation, but are finished
+    // even when no backup is left next to them.
+    let markers: Vec<PathBuf> = args.files.iter().filter_map(|file| throttle::marker_of(file)).map(Path::to_path_buf).collect();
     let backups = get_backups(args);
-    let backups_after_rotation = files_after_rotation(&backups);
-    apply_rotation(&backups, &backups_after_rotation);
+    let backups_after_rotation = rotate(&backups);
+    let (mut plan, kept) = plan_rotation(&backups, &backups_after_rotation, &disposal, compression.as_ref(), layout);
+    if interactive {
+        let planned = plan.len();
+        plan = interactive::review(&backups_after_rotation, plan, std::io::stdin().lock(), std::io::stdout(), Utc::now())
+            .unwrap_or_else(|e| panic!("Failed to review the plan: {}", e));
+        if planned > 0 && plan.is_empty() {
+            println!("No actions approved");
+            return;
+        }
+    }
+    if let Some(output) = output {
+        planfile::write(&plan, output)
+            .unwrap_or_else(|e| panic!("Failed to write the plan to {}: {}", display(output), e));
+        println!("Wrote {} actions to {}", plan.len(), display(output));
+        return;
+    }
+    let paths: Vec<PathBuf> = backups.iter().map(|backup| backup.path.clone()).chain(markers).collect();
+    resume_removals(&paths, root.as_deref(), throttle);
+    let mut journal = create_journal(journal_dir, &run_id, &plan);
+    execute_plan(plan, journal.as_mut(), jobs, throttle);
+    if let Some(grace) = trash_grace {
+        for dir in trash::trash_dirs(&backups) {
+            trash::purge(&dir, grace, Utc::now())
+                .unwrap_or_else(|e| panic!("Failed to purge {}: {}", display(&dir), e));
+        }
+    }
+    let renamed: Vec<(PathBuf, PathBuf)> = backups_after_rotation.as_vec().into_iter()
+        .zip(kept.as_vec())
+        .filter(|(before, after)| before.path != after.path)
+        .map(|(before, after)| (before.path, after.path))
+        .collect();
+    symlink::fix_dangling(&links, &backups, &kept.as_vec(), &renamed, retarget_links);
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
     use file::utils::to_files;
+    use rotationplan::Tier;
     use std::fs;
+    use std::path::PathBuf;
 
     #[test]
     fn get_fs_backups_test() {
@@ -74,11 +350,11 @@ mod test {
 
         let args = Args {
             files: vec![
-                tmp_dir.path().join("a").to_str().unwrap().to_string(),
-                tmp_dir.path().join("b").to_str().unwrap().to_string(),
-                tmp_dir.path().join("c").to_str().unwrap().to_string(),
+                tmp_dir.path().join("a"),
+                tmp_dir.path().join("b"),
+                tmp_dir.path().join("c"),
             ],
-            format: None
+            ..Default::default()
         };
         for file in &args.files {
             let f = fs::File::create(file).unwrap();
@@ -92,16 +368,334 @@ mod test {
     fn get_path_backups_test() {
         let args = Args {
             files: vec![
-                "pg_2024-02-17_03-00-01.tar".to_string(),
-                "pg_2024-02-29_03-00-01.tar".to_string(),
-                "pg_2024-03-12_03-00-01.tar".to_string(),
+                PathBuf::from("pg_2024-02-17_03-00-01.tar"),
+                PathBuf::from("pg_2024-02-29_03-00-01.tar"),
+                PathBuf::from("pg_2024-03-12_03-00-01.tar"),
+            ],
+            format: vec!["pg_%Y-%m-%d_%H-%M-%S.tar".to_string()],
+            ..Default::default()
+        };
+        let backups = get_backups(args);
+        assert_eq!(backups.len(), 3);
+    }
+
+    #[test]
+    fn get_regex_backups_test() {
+        let args = Args {
+            files: vec![
+                PathBuf::from("pg_web01_2024-02-17_job12.tar"),
+                PathBuf::from("pg_web02_2024-02-29_job4312.tar"),
+            ],
+            regex: Some(capture::parse_regex(r"(?<year>\d{4})-(?<month>\d{2})-(?<day>\d{2})").unwrap()),
+            ..Default::default()
+        };
+        let backups = get_backups(args);
+        assert_eq!(backups.len(), 2);
+    }
+
+    #[test]
+    fn get_detected_backups_test() {
+        let args = Args {
+            files: vec![
+                PathBuf::from("pg_2024-02-17T03:00:01Z.tar"),
+                PathBuf::from("pg_20240229_030001.tar"),
+                PathBuf::from("pg-1710212401.tar"),
             ],
-            format: Some("pg_%Y-%m-%d_%H-%M-%S.tar".to_string())
+            detect_date: true,
+            ..Default::default()
         };
         let backups = get_backups(args);
         assert_eq!(backups.len(), 3);
     }
 
+    #[test]
+    fn get_root_backups_test() {
+        let args = Args {
+            files: vec![
+                PathBuf::from("/backups/2024/02/17/dump.tar"),
+                PathBuf::from("/backups/2024/02/17/dump.log"),
+                PathBuf::from("/backups/2024/02/29/dump.tar"),
+            ],
+            format: vec!["%Y/%m/%d/dump.tar".to_string(), "%Y/%m/%d/dump.log".to_string()],
+            root: Some(PathBuf::from("/backups")),
+            ..Default::default()
+        };
+        let backups = get_backups(args);
+        assert_eq!(backups.len(), 2);
+        assert_eq!(backups[0].path, Path::new("/backups/2024/02/17"));
+        assert_eq!(backups[1].path, Path::new("/backups/2024/02/29"));
+    }
+
+    #[test]
+    fn get_sidecar_backups_test() {
+        let tmp_dir = tempdir::TempDir::new("example").unwrap();
+        let mut files = vec![];
+        for (name, time) in [("a", "2024-02-17T03:00:01Z"), ("b", "2024-02-29T03:00:01Z")] {
+            let path = tmp_dir.path().join(name);
+            fs::File::create(&path).unwrap();
+            fs::write(tmp_dir.path().join(format!("{}.meta", name)), format!("time={}", time)).unwrap();
+            files.push(path.clone());
+            files.push(path.with_extension("meta"));
+        }
+        let args = Args {
+            files,
+            time_source: TimeSource::Sidecar("time".to_string()),
+            ..Default::default()
+        };
+        let backups = get_backups(args);
+        assert_eq!(backups.len(), 2);
+        assert_eq!(backups[0].companions, vec![tmp_dir.path().join("a.meta")]);
+    }
+
+    #[test]
+    fn get_backups_skip_mismatch_test() {
+        let args = Args {
+            files: vec![
+                PathBuf::from("pg_2024-02-17_03-00-01.tar"),
+                PathBuf::from("pg_latest.tar"),
+            ],
+            format: vec!["pg_%Y-%m-%d_%H-%M-%S.tar".to_string()],
+            on_mismatch: MismatchPolicy::Skip,
+            ..Default::default()
+        };
+        let backups = get_backups(args);
+        assert_eq!(backups.len(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "Failed to parse path: pg_latest.tar")]
+    fn get_backups_error_mismatch_test() {
+        let args = Args {
+            files: vec![PathBuf::from("pg_latest.tar")],
+            format: vec!["pg_%Y-%m-%d_%H-%M-%S.tar".to_string()],
+            ..Default::default()
+        };
+        get_backups(args);
+    }
+
+    #[test]
+    fn get_xattr_backups_test() {
+        let tmp_dir = tempdir::TempDir::new("example").unwrap();
+        let tagged = tmp_dir.path().join("a");
+        let untagged = tmp_dir.path().join("b");
+        fs::File::create(&tagged).unwrap();
+        fs::File::create(&untagged).unwrap();
+        if xattr::set(&tagged, "user.backup.created", b"2024-05-23T03:00:01Z").is_err() {
+            // The filesystem holding the temporary directory has no user xattrs.
+            return;
+        }
+        let args = Args {
+            files: vec![
+                tagged.clone(),
+                untagged.clone(),
+            ],
+            time_source: TimeSource::Xattr("user.backup.created".to_string()),
+            on_mismatch: MismatchPolicy::Skip,
+            ..Default::default()
+        };
+        let backups = get_backups(args);
+        assert_eq!(backups.len(), 1);
+        assert_eq!(backups[0].path, tagged);
+    }
+
+    #[test]
+    fn get_symlink_backups_test() {
+        let tmp_dir = tempdir::TempDir::new("example").unwrap();
+        let backup = tmp_dir.path().join("pg_2024-02-17.tar");
+        let link = tmp_dir.path().join("latest");
+        fs::File::create(&backup).unwrap();
+        std::os::unix::fs::symlink("pg_2024-02-17.tar", &link).unwrap();
+        let args = |symlinks| Args {
+            files: vec![backup.clone(), link.clone()],
+            symlinks,
+            ..Default::default()
+        };
+        assert_eq!(get_backups(args(SymlinkPolicy::Skip)).len(), 1);
+        assert_eq!(get_backups(args(SymlinkPolicy::Follow)).len(), 2);
+        let backups = get_backups(args(SymlinkPolicy::Target));
+        assert_eq!(backups.len(), 1);
+        assert_eq!(backups[0].path, backup);
+    }
+
+    #[test]
+    fn get_backups_in_progress_test() {
+        let tmp_dir = tempdir::TempDir::new("example").unwrap();
+        let files: Vec<PathBuf> = ["pg_2024-02-17.tar", "pg_2024-02-18.tar", "pg_2024-02-19.tar.partial", "pg_2024-02-18.tar.inprogress"]
+            .iter()
+            .map(|name| tmp_dir.path().join(name))
+            .collect();
+        for file in &files {
+            fs::File::create(file).unwrap();
+        }
+        let args = Args {
+            files: files.clone(),
+            ..Default::default()
+        };
+        let backups = get_backups(args);
+        assert_eq!(backups.len(), 1);
+        assert_eq!(backups[0].path, files[0]);
+
+        let args = Args {
+            files,
+            min_age: Some(std::time::Duration::from_secs(900)),
+            ..Default::default()
+        };
+        assert!(get_backups(args).is_empty());
+    }
+
+    #[test]
+    fn get_companion_backups_test() {
+        let tmp_dir = tempdir::TempDir::new("example").unwrap();
+        let files: Vec<PathBuf> = ["pg_2024-02-17.tar", "pg_2024-02-17.tar.sha256", "pg_2024-02-17.log", "pg_2024-02-18.tar"]
+            .iter()
+            .map(|name| tmp_dir.path().join(name))
+            .collect();
+        for file in &files {
+            fs::File::create(file).unwrap();
+        }
+        let args = Args {
+            files: files.clone(),
+            companion: vec!["{path}.sha256".to_string(), "{stem}.log".to_string()],
+            ..Default::default()
+        };
+        let backups = get_backups(args);
+        assert_eq!(backups.len(), 2);
+        assert_eq!(backups[0].companions, vec![files[1].clone(), files[2].clone()]);
+        assert!(backups[1].companions.is_empty());
+    }
+
+    #[test]
+    fn get_backups_ignores_trash_test() {
+        let args = Args {
+            files: vec![
+                PathBuf::from("pg_2024-02-17.tar"),
+                PathBuf::from(".rotation-trash"),
+                PathBuf::from("/backups/.rotation-trash/20240220T030001Z/pg_2024-02-16.tar"),
+            ],
+            format: vec!["pg_%Y-%m-%d.tar".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(get_backups(args).len(), 1);
+    }
+
+    #[test]
+    fn test_rotation_with_compression() {
+        let tmp_dir = tempdir::TempDir::new("example").unwrap();
+        let mut backups = vec![];
+        for day in 1..=10 {
+            let path = tmp_dir.path().join(format!("pg_{}.tar", day));
+            fs::write(&path, "dump").unwrap();
+            backups.push(file::utils::test_file_at(&path, day));
+        }
+        let after = rotate(&backups);
+        let compression = Compression { codec: compress::Codec::Gzip, tiers: vec![Tier::Weekly, Tier::Monthly] };
+        execute_plan(plan_rotation(&backups, &after, &Disposal::Remove, Some(&compression), None).0, None, 1, None);
+        for file in &after.daily {
+            assert!(file.path.exists());
+        }
+        for file in after.weekly.iter().chain(&after.monthly) {
+            assert!(!file.path.exists());
+            assert!(compress::Codec::Gzip.compressed_path(&file.path).exists());
+        }
+    }
+
+    #[test]
+    fn test_rotation_with_compression_keeps_companions() {
+        let tmp_dir = tempdir::TempDir::new("example").unwrap();
+        for day in 1..=20 {
+            fs::write(tmp_dir.path().join(format!("pg_2024-05-{:02}.tar", day)), "dump").unwrap();
+            fs::write(tmp_dir.path().join(format!("pg_2024-05-{:02}.log", day)), "log").unwrap();
+        }
+        let args = || Args {
+            files: fs::read_dir(tmp_dir.path()).unwrap().map(|entry| entry.unwrap().path()).collect(),
+            format: vec!["pg_%Y-%m-%d.tar".to_string()],
+            companion: vec!["{stem}.log".to_string()],
+            ..Default::default()
+        };
+        let backups = get_backups(args());
+        let after = rotate(&backups);
+        let compression = Compression { codec: compress::Codec::Gzip, tiers: vec![Tier::Weekly, Tier::Monthly] };
+        execute_plan(plan_rotation(&backups, &after, &Disposal::Remove, Some(&compression), None).0, None, 1, None);
+
+        // The next run still sees the logs as companions of the compressed backups.
+        let backups = get_backups(args());
+        assert_eq!(backups.len(), after.as_vec().len());
+        assert!(backups.iter().any(|backup| compress::is_compressed(&backup.path)));
+        for backup in &backups {
+            assert_eq!(backup.companions.len(), 1);
+        }
+    }
+
+    #[test]
+    fn create_journal_test() {
+        let tmp_dir = tempdir::TempDir::new("example").unwrap();
+        let journal_dir = tmp_dir.path().join("journal");
+        assert!(create_journal(Some(journal_dir.clone()), "run", &Plan::new()).is_none());
+        assert!(!journal_dir.exists());
+
+        let plan = vec![action::Action::new(ActionType::Remove, file::utils::test_file("a.tar", 1), "")];
+        assert!(create_journal(Some(journal_dir.clone()), "run", &plan).is_some());
+        assert!(journal::path(&journal_dir, "run").exists());
+    }
+
+    #[test]
+    fn get_root_backups_skips_interrupted_removals_test() {
+        let args = Args {
+            files: vec![
+                PathBuf::from("/backups/2024/05/23/dump.tar"),
+                PathBuf::from("/backups/2024/05/22.rotation-deleting/dump.tar"),
+            ],
+            format: vec!["%Y/%m/%d/dump.tar".to_string()],
+            root: Some(PathBuf::from("/backups")),
+            ..Default::default()
+        };
+        let backups = get_backups(args);
+        assert_eq!(backups.len(), 1);
+        assert_eq!(backups[0].path, PathBuf::from("/backups/2024/05/23"));
+    }
+
+    #[test]
+    fn get_backups_hard_links_test() {
+        let tmp_dir = tempdir::TempDir::new("example").unwrap();
+        let backup = tmp_dir.path().join("a");
+        let link = tmp_dir.path().join("b");
+        fs::File::create(&backup).unwrap();
+        fs::hard_link(&backup, &link).unwrap();
+        let args = |layout| Args {
+            files: vec![backup.clone(), link.clone()],
+            layout,
+            ..Default::default()
+        };
+        assert_eq!(get_backups(args(None)).len(), 2);
+        let backups = get_backups(args(Some(Layout::Hardlink)));
+        assert_eq!(backups.len(), 1);
+        assert_eq!(backups[0].path, backup);
+    }
+
+    #[test]
+    fn test_rotation_with_layout() {
+        for layout in [Layout::Hardlink, Layout::Move] {
+            let tmp_dir = tempdir::TempDir::new("example").unwrap();
+            let mut backups = vec![];
+            for day in 1..=10 {
+                let path = tmp_dir.path().join(format!("pg_{}.tar", day));
+                fs::write(&path, "dump").unwrap();
+                backups.push(file::utils::test_file_at(&path, day));
+            }
+            let after = rotate(&backups);
+            execute_plan(plan_rotation(&backups, &after, &Disposal::Remove, None, Some(layout)).0, None, 1, None);
+            for file in &after.daily {
+                let linked = tmp_dir.path().join("daily").join(file.path.file_name().unwrap());
+                assert!(linked.exists());
+                assert_eq!(file.path.exists(), layout == Layout::Hardlink);
+            }
+
+            // A second run over the same files changes nothing.
+            let files = layout::expand(fs::read_dir(tmp_dir.path()).unwrap().map(|entry| entry.unwrap().path()).collect(), layout);
+            assert_eq!(files.len(), after.as_vec().len());
+        }
+    }
+
     #[test]
     fn test_rotation() {
         let tmp = to_files(vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10]);
@@ -109,10 +703,10 @@ mod test {
         for b in tmp {
             backups.push(b);
         }
-        let backups_after_rotation = files_after_rotation(&backups);
-        apply_rotation(&backups, &backups_after_rotation);
+        let backups_after_rotation = rotate(&backups);
+        execute_plan(plan_rotation(&backups, &backups_after_rotation, &Disposal::Remove, None, None).0, None, 1, None);
         assert_eq!(backups.len(), 10);
-        assert_eq!(backups_after_rotation.len(), 5);
+        assert_eq!(backups_after_rotation.as_vec().len(), 5);
     }
 }
 
@@ -0,0 +1,61 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+// Runs `f` on the items with at most `jobs` threads. Each result is handed to
+// `emit` on the calling thread in the order of the items, as soon as it and
+// all the results before it are ready, so output does not depend on timing.
+pub fn for_each_ordered<T: Sync, R: Send>(
+    items: &[T],
+    jobs: usize,
+    f: impl Fn(&T) -> R + Sync,
+    mut emit: impl FnMut(&T, R),
+) {
+    let next = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel();
+    thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, items.len().max(1)) {
+            let sender = sender.clone();
+            let (next, f) = (&next, &f);
+            scope.spawn(move || loop {
+                let i = next.fetch_add(1, Ordering::SeqCst);
+                if i >= items.len() || sender.send((i, f(&items[i]))).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(sender);
+        let mut ready = BTreeMap::new();
+        let mut emitted = 0;
+        for (i, result) in receiver {
+            ready.insert(i, result);
+            while let Some(result) = ready.remove(&emitted) {
+                emit(&items[emitted], result);
+                emitted += 1;
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_for_each_ordered() {
+        let items: Vec<u64> = (0..20).collect();
+        let mut emitted = vec![];
+        for_each_ordered(&items, 4, |item| {
+            // Later items finish first.
+            thread::sleep(Duration::from_millis(20 - item));
+            item * 2
+        }, |item, result| emitted.push((*item, result)));
+        assert_eq!(emitted, items.iter().map(|item| (*item, item * 2)).collect::<Vec<_>>());
+
+        let mut emitted = vec![];
+        for_each_ordered(&[] as &[u64], 4, |item| *item, |item, _| emitted.push(*item));
+        assert!(emitted.is_empty());
+    }
+}
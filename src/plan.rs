@@ -1,26 +1,67 @@
 use crate::file::File;
 use std::collections::HashSet;
+use std::path::PathBuf;
 use crate::action::{ActionType, Action};
+use crate::compress::{self, Compression};
+use crate::rotationplan::RotationPlan;
+use crate::layout;
+use crate::trash;
 
 pub type Plan = Vec<Action>;
 
-pub fn create_plan(before: &Vec<File>, after: &Vec<File>) -> Plan {
+// What happens to backups dropped from the rotation.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Disposal {
+    #[default]
+    Remove,
+    Archive(PathBuf),
+    Trash(String),
+}
+
+pub fn create_plan(before: &Vec<File>, after: &Vec<File>, disposal: &Disposal) -> Plan {
         let mut plan = Plan::new();
         let mut seen = HashSet::new();
         for file in after {
             seen.insert(file.path.clone());
+            // A companion shared with a kept backup stays with it.
+            seen.extend(file.companions.iter().cloned());
         }
         for file in before {
             if !seen.contains(&file.path) {
+                let mut file = file.clone();
+                file.companions.retain(|companion| !seen.contains(companion));
+                let type_ = match disposal {
+                    Disposal::Remove => ActionType::Remove,
+                    Disposal::Archive(dir) => ActionType::Move { dest: dir.join(file.relative_path()) },
+                    Disposal::Trash(run_id) => ActionType::Move { dest: trash::trash_path(&file, run_id) },
+                };
                 plan.push(Action::new(
-                    ActionType::Remove,
-                    file.clone()
+                    type_,
+                    file,
+                    "not kept by the rotation"
                 ));
             }
         }
         plan
 }
 
+// Kept backups in the compressed tiers are compressed in place. Directories
+// and backups that are already compressed are left as they are.
+pub fn compress_plan(after: &RotationPlan<File>, compression: &Compression) -> Plan {
+    let mut plan = Plan::new();
+    for tier in &compression.tiers {
+        for file in after.tier(*tier) {
+            if compress::is_compressed(&file.path) || file.path.is_dir() || plan.iter().any(|action| action.file == *file) {
+                continue;
+            }
+            let reason = format!("kept in the {} tier", layout::tier_name(*tier));
+            plan.push(Action::new(ActionType::Compress { codec: compression.codec }, file.clone(), &reason));
+        }
+    }
+    plan
+}
+
+
 
 #[cfg(test)]
 mod test {
@@ -28,6 +69,9 @@ mod test {
 use super::*;
 use crate::file::utils::test_file;
 use crate::action::ActionType;
+use std::path::Path;
+use crate::compress::Codec;
+use crate::rotationplan::Tier;
 
 #[test]
 fn test_plan() {
@@ -41,13 +85,58 @@ fn test_plan() {
         test_file("b", 2),
         test_file("c", 3),
     ];
-    let plan = create_plan(&before, &after);
+    let plan = create_plan(&before, &after, &Disposal::Remove);
     assert_eq!(plan.len(), 2, "Expected 2 actions, got {}", plan.len());
     assert_eq!(plan[0].type_, ActionType::Remove);
-    assert_eq!(plan[0].file.path, "a");
+    assert_eq!(plan[0].file.path, Path::new("a"));
     assert_eq!(plan[1].type_, ActionType::Remove);
-    assert_eq!(plan[1].file.path, "d");
+    assert_eq!(plan[1].file.path, Path::new("d"));
 }
 
+#[test]
+fn test_plan_keeps_shared_companions() {
+    let mut removed = test_file("a.tar", 1);
+    removed.companions = vec![PathBuf::from("a.log"), PathBuf::from("index")];
+    let mut kept = test_file("b.tar", 2);
+    kept.companions = vec![PathBuf::from("index")];
+    let plan = create_plan(&vec![removed, kept.clone()], &vec![kept], &Disposal::Remove);
+    assert_eq!(plan.len(), 1);
+    assert_eq!(plan[0].file.companions, vec![PathBuf::from("a.log")]);
 }
 
+#[test]
+fn test_plan_archive() {
+    let before = vec![test_file("/backups/a.tar", 1), test_file("/backups/b.tar", 2)];
+    let after = vec![test_file("/backups/b.tar", 2)];
+    let plan = create_plan(&before, &after, &Disposal::Archive(PathBuf::from("/archive")));
+    assert_eq!(plan.len(), 1);
+    assert_eq!(plan[0].type_, ActionType::Move { dest: PathBuf::from("/archive/a.tar") });
+
+    let mut dated = test_file("/backups/2024/05/23", 1);
+    dated.root = Some(PathBuf::from("/backups"));
+    let plan = create_plan(&vec![dated], &vec![], &Disposal::Archive(PathBuf::from("/archive")));
+    assert_eq!(plan[0].type_, ActionType::Move { dest: PathBuf::from("/archive/2024/05/23") });
+}
+
+#[test]
+fn test_compress_plan() {
+    let after = RotationPlan {
+        daily: vec![test_file("c.tar", 3)].into(),
+        weekly: vec![test_file("b.tar", 2), test_file("a.tar.zst", 1)].into(),
+        monthly: vec![test_file("b.tar", 2)].into(),
+    };
+    let compression = Compression { codec: Codec::Zstd, tiers: vec![Tier::Weekly, Tier::Monthly] };
+    let plan = compress_plan(&after, &compression);
+    assert_eq!(plan.len(), 1);
+    assert_eq!(plan[0].type_, ActionType::Compress { codec: Codec::Zstd });
+    assert_eq!(plan[0].file.path, Path::new("b.tar"));
+}
+
+#[test]
+fn test_plan_trash() {
+    let before = vec![test_file("/backups/a.tar", 1)];
+    let plan = create_plan(&before, &vec![], &Disposal::Trash("20240523T030001Z".to_string()));
+    assert_eq!(plan[0].type_, ActionType::Move { dest: PathBuf::from("/backups/.rotation-trash/20240523T030001Z/a.tar") });
+}
+
+}
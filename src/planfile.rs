@@ -0,0 +1,147 @@
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use serde_json::{json, Value};
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+use crate::action::{Action, ActionType};
+use crate::compress::Codec;
+use crate::dated::Dated;
+use crate::display::display;
+use crate::file::File;
+use crate::journal::{from_value, to_value};
+use crate::plan::Plan;
+
+// Each action records the size, modification time and inode its file had
+// when planned, so that `apply` can refuse to act on files that changed.
+pub fn write(plan: &Plan, path: &Path) -> io::Result<()> {
+    let actions: Vec<Value> = plan.iter().map(|action| {
+        let (name, dest, codec) = match &action.type_ {
+            ActionType::Remove => ("remove", None, None),
+            ActionType::Move { dest } => ("move", Some(to_value(dest)), None),
+            ActionType::Compress { codec } => ("compress", None, codec.to_possible_value().map(|value| value.get_name().to_string())),
+            ActionType::Link { dest } => ("link", Some(to_value(dest)), None),
+        };
+        json!({
+            "action": name,
+            "dest": dest,
+            "codec": codec,
+            "reason": action.reason,
+            "path": to_value(&action.file.path),
+            "root": action.file.root.as_deref().map(to_value),
+            "companions": action.file.companions.iter().map(|companion| to_value(companion)).collect::<Vec<Value>>(),
+            "date": action.file.get_date().to_rfc3339(),
+            "identity": identity(&action.file.path),
+        })
+    }).collect();
+    let content = serde_json::to_string_pretty(&json!({ "actions": actions })).map_err(io::Error::other)?;
+    fs::write(path, content + "\n")
+}
+
+// Files that did not exist when planned, such as the compressed copy a later
+// action links, are not checked.
+pub fn read(path: &Path) -> Result<Plan, String> {
+    let content = fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", display(path), e))?;
+    let content: Value = serde_json::from_str(&content).map_err(|e| format!("Invalid plan {}: {}", display(path), e))?;
+    let entries = content["actions"].as_array().ok_or_else(|| format!("Invalid plan {}: no actions", display(path)))?;
+    let mut plan = Plan::new();
+    for entry in entries {
+        let action = read_action(entry)?;
+        let planned = &entry["identity"];
+        if !planned.is_null() && identity(&action.file.path) != *planned {
+            return Err(format!("{} changed since the plan was made", display(&action.file.path)));
+        }
+        plan.push(action);
+    }
+    Ok(plan)
+}
+
+fn identity(path: &Path) -> Value {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) => json!({
+            "size": metadata.size(),
+            "mtime": metadata.mtime(),
+            "mtime_nsec": metadata.mtime_nsec(),
+            "inode": metadata.ino(),
+        }),
+        Err(_) => Value::Null,
+    }
+}
+
+fn read_action(entry: &Value) -> Result<Action, String> {
+    let invalid = |field: &str| format!("Invalid {} in plan: {}", field, entry);
+    let path = from_value(&entry["path"]).ok_or_else(|| invalid("path"))?;
+    let date = entry["date"].as_str()
+        .and_then(|date| DateTime::parse_from_rfc3339(date).ok())
+        .ok_or_else(|| invalid("date"))?;
+    let mut file = File::new(path, date.with_timezone(&Utc));
+    file.root = from_value(&entry["root"]);
+    for companion in entry["companions"].as_array().into_iter().flatten() {
+        file.companions.push(from_value(companion).ok_or_else(|| invalid("companion"))?);
+    }
+    let dest = || from_value(&entry["dest"]).ok_or_else(|| invalid("dest"));
+    let type_ = match entry["action"].as_str() {
+        Some("remove") => ActionType::Remove,
+        Some("move") => ActionType::Move { dest: dest()? },
+        Some("link") => ActionType::Link { dest: dest()? },
+        Some("compress") => {
+            let codec = entry["codec"].as_str().and_then(|codec| Codec::from_str(codec, false).ok());
+            ActionType::Compress { codec: codec.ok_or_else(|| invalid("codec"))? }
+        }
+        _ => return Err(invalid("action")),
+    };
+    Ok(Action::new(type_, file, entry["reason"].as_str().unwrap_or_default()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::file::utils::test_file_at;
+    use std::path::PathBuf;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_round_trip() {
+        let tmp_dir = TempDir::new("example").unwrap();
+        let a = tmp_dir.path().join("a.tar");
+        let b = tmp_dir.path().join("b.tar");
+        fs::write(&a, "dump").unwrap();
+        fs::write(&b, "dump").unwrap();
+        let mut removed = test_file_at(&a, 1);
+        removed.companions.push(tmp_dir.path().join("a.tar.sha256"));
+        let plan = vec![
+            Action::new(ActionType::Move { dest: tmp_dir.path().join("archive/a.tar") }, removed, "not kept by the rotation"),
+            Action::new(ActionType::Compress { codec: Codec::Zstd }, test_file_at(&b, 2), "kept in the weekly tier"),
+            Action::new(ActionType::Link { dest: tmp_dir.path().join("weekly/b.tar.zst") }, test_file_at(&b.with_extension("tar.zst"), 2), ""),
+        ];
+        let plan_path = tmp_dir.path().join("plan.json");
+        write(&plan, &plan_path).unwrap();
+
+        let loaded = read(&plan_path).unwrap();
+        assert_eq!(loaded.len(), 3);
+        for (action, planned) in loaded.iter().zip(&plan) {
+            assert_eq!(action.type_, planned.type_);
+            assert_eq!(action.file.path, planned.file.path);
+            assert_eq!(action.file.companions, planned.file.companions);
+            assert_eq!(action.file.get_date(), planned.file.get_date());
+            assert_eq!(action.reason, planned.reason);
+        }
+    }
+
+    #[test]
+    fn test_changed_file() {
+        let tmp_dir = TempDir::new("example").unwrap();
+        let a = tmp_dir.path().join("a.tar");
+        fs::write(&a, "dump").unwrap();
+        let plan_path = tmp_dir.path().join("plan.json");
+        write(&vec![Action::new(ActionType::Remove, test_file_at(&a, 1), "")], &plan_path).unwrap();
+
+        fs::write(&a, "newer dump").unwrap();
+        assert!(read(&plan_path).unwrap_err().contains("changed since the plan was made"));
+        fs::remove_file(&a).unwrap();
+        assert!(read(&plan_path).is_err());
+        assert!(read(&PathBuf::from("missing.json")).is_err());
+    }
+}
@@ -0,0 +1,127 @@
+use chrono::format::{Fixed, Item, Numeric, StrftimeItems};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Precision {
+    Second,
+    Minute,
+    Hour,
+    Day,
+    Week,
+    Month,
+    Year,
+}
+
+impl fmt::Display for Precision {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Precision::Second => "second",
+            Precision::Minute => "minute",
+            Precision::Hour => "hour",
+            Precision::Day => "day",
+            Precision::Week => "week",
+            Precision::Month => "month",
+            Precision::Year => "year",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Default)]
+struct Fields {
+    year: bool,
+    isoyear: bool,
+    month: bool,
+    isoweek: bool,
+    day: bool,
+    hour: bool,
+    minute: bool,
+    second: bool,
+    timestamp: bool,
+}
+
+pub fn precision(format: &str) -> Result<Precision, String> {
+    let mut fields = Fields::default();
+    for item in StrftimeItems::new(format) {
+        match item {
+            Item::Numeric(Numeric::Year, _) | Item::Numeric(Numeric::YearMod100, _) => fields.year = true,
+            Item::Numeric(Numeric::IsoYear, _) | Item::Numeric(Numeric::IsoYearMod100, _) => fields.isoyear = true,
+            Item::Numeric(Numeric::Month, _) => fields.month = true,
+            Item::Fixed(Fixed::ShortMonthName) | Item::Fixed(Fixed::LongMonthName) => fields.month = true,
+            Item::Numeric(Numeric::IsoWeek, _) => fields.isoweek = true,
+            Item::Numeric(Numeric::Day, _) => fields.day = true,
+            Item::Numeric(Numeric::Ordinal, _) => {
+                fields.month = true;
+                fields.day = true;
+            }
+            Item::Numeric(Numeric::Hour, _) | Item::Numeric(Numeric::Hour12, _) => fields.hour = true,
+            Item::Numeric(Numeric::Minute, _) => fields.minute = true,
+            Item::Numeric(Numeric::Second, _) => fields.second = true,
+            Item::Numeric(Numeric::Timestamp, _) => fields.timestamp = true,
+            Item::Fixed(Fixed::RFC3339) => return Ok(Precision::Second),
+            Item::Error => return Err(format!("invalid format: {}", format)),
+            _ => {}
+        }
+    }
+
+    if fields.timestamp {
+        return Ok(Precision::Second);
+    }
+    let date = if fields.isoweek {
+        if !fields.isoyear {
+            return Err(format!("%V requires the ISO week year %G: {}", format));
+        }
+        Precision::Week
+    } else if fields.year {
+        match (fields.month, fields.day) {
+            (true, true) => Precision::Day,
+            (true, false) => Precision::Month,
+            (false, false) => Precision::Year,
+            (false, true) => return Err(format!("day without a month: {}", format)),
+        }
+    } else {
+        return Err(format!("format has no year: {}", format));
+    };
+
+    match (fields.hour, fields.minute, fields.second) {
+        (false, false, false) => Ok(date),
+        _ if date > Precision::Day => Err(format!("time of day without a day: {}", format)),
+        (true, false, false) => Ok(Precision::Hour),
+        (true, true, false) => Ok(Precision::Minute),
+        (true, true, true) => Ok(Precision::Second),
+        _ => Err(format!("incomplete time of day: {}", format)),
+    }
+}
+
+pub fn parse_format(format: &str) -> Result<String, String> {
+    precision(format).map(|_| format.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_precision() {
+        assert_eq!(precision("pg_%Y-%m-%d_%H-%M-%S.tar"), Ok(Precision::Second));
+        assert_eq!(precision("pg_%Y-%m-%d_%H-%M.tar"), Ok(Precision::Minute));
+        assert_eq!(precision("pg_%Y-%m-%d_%H.tar"), Ok(Precision::Hour));
+        assert_eq!(precision("db_%Y-%m-%d.sql.gz"), Ok(Precision::Day));
+        assert_eq!(precision("db_%Y-%j.sql.gz"), Ok(Precision::Day));
+        assert_eq!(precision("weekly_%G-W%V.tar"), Ok(Precision::Week));
+        assert_eq!(precision("archive_%Y-%m.tar"), Ok(Precision::Month));
+        assert_eq!(precision("archive_%b-%Y.tar"), Ok(Precision::Month));
+        assert_eq!(precision("archive_%Y.tar"), Ok(Precision::Year));
+        assert_eq!(precision("backup-%s.tar"), Ok(Precision::Second));
+    }
+
+    #[test]
+    fn test_invalid_format() {
+        assert!(precision("pg_%m-%d.tar").is_err());
+        assert!(precision("pg_%Y-%d.tar").is_err());
+        assert!(precision("weekly_%Y-W%V.tar").is_err());
+        assert!(precision("archive_%Y-%m_%H.tar").is_err());
+        assert!(precision("pg_%Y-%m-%d_%M.tar").is_err());
+        assert!(precision("pg_%Y-%m-%d_%Q.tar").is_err());
+    }
+}
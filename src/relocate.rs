@@ -0,0 +1,158 @@
+use std::fs;
+use std::io::{self, Read};
+use std::os::unix::fs::symlink;
+use std::path::Path;
+
+use crate::display::display;
+use crate::suffix;
+
+// EXDEV, returned by rename(2) across filesystems.
+const CROSS_DEVICE: i32 = 18;
+
+// Moves a file or directory tree, falling back to a verified copy when `dest`
+// is on another filesystem. The source is only deleted once the copy has
+// been synced, compared and renamed into place, so a failure never loses data.
+pub fn move_path(src: &Path, dest: &Path) -> io::Result<()> {
+    if dest.symlink_metadata().is_ok() {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, "destination already exists"));
+    }
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    match fs::rename(src, dest) {
+        Err(e) if e.raw_os_error() == Some(CROSS_DEVICE) => copy_then_delete(src, dest),
+        result => result,
+    }
+}
+
+pub fn copy_then_delete(src: &Path, dest: &Path) -> io::Result<()> {
+    let tmp = &suffix::tmp_path(dest);
+    if tmp.symlink_metadata().is_ok() {
+        remove(tmp)?;
+    }
+    copy(src, tmp)?;
+    verify(src, tmp)?;
+    fs::rename(tmp, dest)?;
+    if let Some(parent) = dest.parent() {
+        fs::File::open(parent)?.sync_all()?;
+    }
+    remove(src)
+}
+
+fn copy(src: &Path, dest: &Path) -> io::Result<()> {
+    let metadata = src.symlink_metadata()?;
+    if metadata.is_symlink() {
+        symlink(fs::read_link(src)?, dest)?;
+    } else if metadata.is_dir() {
+        fs::create_dir(dest)?;
+        for entry in fs::read_dir(src)? {
+            let entry = entry?;
+            copy(&entry.path(), &dest.join(entry.file_name()))?;
+        }
+        fs::set_permissions(dest, metadata.permissions())?;
+        fs::File::open(dest)?.sync_all()?;
+    } else {
+        fs::copy(src, dest)?;
+        // The modification time is kept, since later runs may date the backup by it.
+        let file = fs::OpenOptions::new().write(true).open(dest)?;
+        file.set_modified(metadata.modified()?)?;
+        file.sync_all()?;
+    }
+    Ok(())
+}
+
+fn verify(src: &Path, dest: &Path) -> io::Result<()> {
+    let metadata = src.symlink_metadata()?;
+    let copied = dest.symlink_metadata()?;
+    let same = if metadata.is_symlink() {
+        copied.is_symlink() && fs::read_link(src)? == fs::read_link(dest)?
+    } else if metadata.is_dir() {
+        let mut entries = vec![];
+        for entry in fs::read_dir(src)? {
+            let entry = entry?;
+            verify(&entry.path(), &dest.join(entry.file_name()))?;
+            entries.push(entry.file_name());
+        }
+        copied.is_dir() && fs::read_dir(dest)?.count() == entries.len()
+    } else {
+        copied.is_file() && copied.len() == metadata.len() && same_content(src, dest)?
+    };
+    match same {
+        true => Ok(()),
+        false => Err(io::Error::new(io::ErrorKind::InvalidData, format!("copy of {} does not match", display(src)))),
+    }
+}
+
+fn same_content(a: &Path, b: &Path) -> io::Result<bool> {
+    let mut a = io::BufReader::new(fs::File::open(a)?);
+    let mut b = io::BufReader::new(fs::File::open(b)?);
+    let mut buf_a = [0u8; 8192];
+    let mut buf_b = [0u8; 8192];
+    loop {
+        let len = a.read(&mut buf_a)?;
+        if len == 0 {
+            return Ok(b.read(&mut buf_b)? == 0);
+        }
+        b.read_exact(&mut buf_b[..len])?;
+        if buf_a[..len] != buf_b[..len] {
+            return Ok(false);
+        }
+    }
+}
+
+fn remove(path: &Path) -> io::Result<()> {
+    match path.symlink_metadata()?.is_dir() {
+        true => fs::remove_dir_all(path),
+        false => fs::remove_file(path),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_move_path() {
+        let tmp_dir = TempDir::new("example").unwrap();
+        let src = tmp_dir.path().join("a.tar");
+        let dest = tmp_dir.path().join("archive/a.tar");
+        fs::write(&src, "dump").unwrap();
+        move_path(&src, &dest).unwrap();
+        assert!(!src.exists());
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "dump");
+
+        fs::write(&src, "other").unwrap();
+        assert!(move_path(&src, &dest).is_err());
+        assert!(src.exists());
+    }
+
+    #[test]
+    fn test_copy_then_delete() {
+        let tmp_dir = TempDir::new("example").unwrap();
+        let src = tmp_dir.path().join("2024-05-23");
+        let dest = tmp_dir.path().join("archive");
+        fs::create_dir_all(src.join("db")).unwrap();
+        fs::write(src.join("db/dump.sql"), "SELECT 1;").unwrap();
+        symlink("db/dump.sql", src.join("latest")).unwrap();
+        let modified = fs::metadata(src.join("db/dump.sql")).unwrap().modified().unwrap();
+
+        copy_then_delete(&src, &dest).unwrap();
+        assert!(!src.exists());
+        assert_eq!(fs::read_to_string(dest.join("db/dump.sql")).unwrap(), "SELECT 1;");
+        assert_eq!(fs::metadata(dest.join("db/dump.sql")).unwrap().modified().unwrap(), modified);
+        assert_eq!(fs::read_link(dest.join("latest")).unwrap(), Path::new("db/dump.sql"));
+    }
+
+    #[test]
+    fn test_verify() {
+        let tmp_dir = TempDir::new("example").unwrap();
+        let a = tmp_dir.path().join("a");
+        let b = tmp_dir.path().join("b");
+        fs::write(&a, "dump").unwrap();
+        fs::write(&b, "dumb").unwrap();
+        assert!(verify(&a, &b).is_err());
+        fs::write(&b, "dump").unwrap();
+        assert!(verify(&a, &b).is_ok());
+    }
+}
@@ -2,8 +2,16 @@ use std::collections::VecDeque;
 use std::fmt::Debug;
 use std::cmp::Eq;
 
+use clap::ValueEnum;
+
 use crate::dated::Dated;
 
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Tier {
+    Daily,
+    Weekly,
+    Monthly,
+}
 
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub struct RotationPlan<T: Clone + Dated> {
@@ -21,6 +29,22 @@ impl<T: Clone + Dated> RotationPlan<T> {
         }
     }
 
+    pub fn tier(&self, tier: Tier) -> &VecDeque<T> {
+        match tier {
+            Tier::Daily => &self.daily,
+            Tier::Weekly => &self.weekly,
+            Tier::Monthly => &self.monthly,
+        }
+    }
+
+    pub fn map<F: Fn(&T) -> T>(&self, f: F) -> RotationPlan<T> {
+        RotationPlan {
+            daily: self.daily.iter().map(&f).collect(),
+            weekly: self.weekly.iter().map(&f).collect(),
+            monthly: self.monthly.iter().map(&f).collect(),
+        }
+    }
+
     pub fn as_vec(&self) -> Vec<T> {
         let mut v = Vec::new();
         for backup in &self.daily {
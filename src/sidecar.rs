@@ -0,0 +1,103 @@
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use crate::display::display;
+
+use crate::suffix;
+use crate::timesource::parse_timestamp;
+
+pub const EXTENSIONS: [&str; 2] = ["json", "meta"];
+
+pub fn find(path: &Path) -> Option<PathBuf> {
+    EXTENSIONS.iter()
+        .map(|extension| suffix::append(path, extension))
+        .find(|sidecar| sidecar.exists())
+}
+
+pub fn is_sidecar_of(path: &Path, files: &HashSet<&Path>) -> bool {
+    EXTENSIONS.iter().any(|extension| {
+        let suffix = format!(".{}", extension);
+        match path.as_os_str().as_bytes().strip_suffix(suffix.as_bytes()) {
+            Some(primary) => files.contains(Path::new(OsStr::from_bytes(primary))),
+            None => false,
+        }
+    })
+}
+
+// `<backup>.json` is searched for a dotted key such as `snapshot.time`, and
+// `<backup>.meta` for a `key=value` line.
+pub fn read_timestamp(sidecar: &Path, key: &str) -> Result<DateTime<Utc>, String> {
+    let content = fs::read_to_string(sidecar).map_err(|e| format!("Failed to read {}: {}", display(sidecar), e))?;
+    let value = match sidecar.extension().and_then(|extension| extension.to_str()) {
+        Some("json") => json_value(&content, key),
+        _ => meta_value(&content, key),
+    };
+    let value = value.ok_or_else(|| format!("No {} field in {}", key, display(sidecar)))?;
+    parse_timestamp(&value).ok_or_else(|| format!("Invalid {} field in {}: {}", key, display(sidecar), value))
+}
+
+
+fn json_value(content: &str, key: &str) -> Option<String> {
+    let json: Value = serde_json::from_str(content).ok()?;
+    let pointer = format!("/{}", key.replace('.', "/"));
+    match json.pointer(&pointer)? {
+        Value::String(value) => Some(value.clone()),
+        Value::Number(value) => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+fn meta_value(content: &str, key: &str) -> Option<String> {
+    content.lines()
+        .filter_map(|line| line.split_once('='))
+        .find(|(name, _)| name.trim() == key)
+        .map(|(_, value)| value.trim().trim_matches('"').to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_json() {
+        let tmp_dir = tempdir::TempDir::new("example").unwrap();
+        let backup = tmp_dir.path().join("dump.tar");
+        fs::write(tmp_dir.path().join("dump.tar.json"), r#"{"snapshot": {"time": "2024-05-23T05:00:01+02:00"}, "epoch": 1716433201, "status": "ok"}"#).unwrap();
+        let sidecar = find(&backup).unwrap();
+        let expected = Utc.with_ymd_and_hms(2024, 5, 23, 3, 0, 1).unwrap();
+        assert_eq!(read_timestamp(&sidecar, "snapshot.time"), Ok(expected));
+        assert_eq!(read_timestamp(&sidecar, "epoch"), Ok(expected));
+        assert!(read_timestamp(&sidecar, "status").is_err());
+        assert!(read_timestamp(&sidecar, "missing").is_err());
+    }
+
+    #[test]
+    fn test_meta() {
+        let tmp_dir = tempdir::TempDir::new("example").unwrap();
+        let backup = tmp_dir.path().join("dump.tar");
+        fs::write(tmp_dir.path().join("dump.tar.meta"), "status=ok\nsnapshot_time = \"2024-05-23T03:00:01Z\"\nsize=42\n").unwrap();
+        let sidecar = find(&backup).unwrap();
+        assert_eq!(read_timestamp(&sidecar, "snapshot_time"), Ok(Utc.with_ymd_and_hms(2024, 5, 23, 3, 0, 1).unwrap()));
+        assert!(read_timestamp(&sidecar, "status").is_err());
+    }
+
+    #[test]
+    fn test_find() {
+        let tmp_dir = tempdir::TempDir::new("example").unwrap();
+        assert!(find(&tmp_dir.path().join("dump.tar")).is_none());
+    }
+
+    #[test]
+    fn test_is_sidecar_of() {
+        let files = HashSet::from([Path::new("dump.tar"), Path::new("dump.tar.json"), Path::new("other.json")]);
+        assert!(is_sidecar_of(Path::new("dump.tar.json"), &files));
+        assert!(!is_sidecar_of(Path::new("other.json"), &files));
+        assert!(!is_sidecar_of(Path::new("dump.tar"), &files));
+    }
+}
@@ -0,0 +1,29 @@
+use std::path::{Path, PathBuf};
+
+// Copies and links are first written under this extension and then renamed
+// into place, so an interrupted run can leave one behind.
+pub const TMP: &str = "rotation-tmp";
+
+// Appends `.<extension>` to the whole file name, where `with_extension`
+// would replace the last extension.
+pub fn append(path: &Path, extension: &str) -> PathBuf {
+    let mut appended = path.as_os_str().to_os_string();
+    appended.push(".");
+    appended.push(extension);
+    PathBuf::from(appended)
+}
+
+pub fn tmp_path(path: &Path) -> PathBuf {
+    append(path, TMP)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_append() {
+        assert_eq!(append(Path::new("/backups/a.tar"), "gz"), Path::new("/backups/a.tar.gz"));
+        assert_eq!(tmp_path(Path::new("a.tar.gz")), Path::new("a.tar.gz.rotation-tmp"));
+    }
+}
@@ -0,0 +1,146 @@
+use clap::ValueEnum;
+use std::fs;
+use std::io;
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+
+use crate::dated::Dated;
+use crate::display::display;
+use crate::file::File;
+use crate::suffix;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, ValueEnum)]
+pub enum SymlinkPolicy {
+    /// Leave symlinks out of the rotation
+    #[default]
+    Skip,
+    /// Rotate the symlink as a backup of its own, dated from its target
+    Follow,
+    /// Rotate the target of the symlink in its place
+    Target,
+}
+
+pub fn is_symlink(path: &Path) -> bool {
+    fs::symlink_metadata(path).map(|metadata| metadata.file_type().is_symlink()).unwrap_or(false)
+}
+
+pub fn resolve(link: &Path) -> io::Result<PathBuf> {
+    let target = fs::read_link(link)?;
+    Ok(match link.parent() {
+        Some(parent) => parent.join(target),
+        None => target,
+    })
+}
+
+// Links whose target was moved or compressed follow it, and links whose
+// target was rotated away are pointed at the newest kept backup. Without
+// `retarget` both are reported so that they can be fixed by hand. `renamed`
+// pairs the kept backups that moved with their new paths.
+pub fn fix_dangling(links: &[PathBuf], before: &[File], kept: &[File], renamed: &[(PathBuf, PathBuf)], retarget: bool) {
+    let newest = kept.iter()
+        .filter(|file| !is_symlink(&file.path) && file.path.exists())
+        .max_by_key(|file| file.get_date());
+    for link in links {
+        let target = match resolve(link) {
+            Ok(target) => target,
+            Err(_) => continue,
+        };
+        if target.exists() || !before.iter().any(|file| file.path == target) {
+            continue;
+        }
+        let moved = renamed.iter().find(|(from, to)| *from == target && to.exists()).map(|(_, to)| to);
+        match (retarget, moved, newest) {
+            (true, Some(moved), _) => {
+                println!("Following moved backup: {} -> {}", display(link), display(moved));
+                replace(link, moved).unwrap();
+            }
+            (false, Some(moved), _) => {
+                println!("Warning: symlink {} points to moved backup {}, now at {}", display(link), display(&target), display(moved))
+            }
+            (true, None, Some(newest)) => {
+                println!("Retargeting symlink: {} -> {}", display(link), display(&newest.path));
+                replace(link, &newest.path).unwrap();
+            }
+            _ => println!("Warning: symlink {} points to removed backup {}", display(link), display(&target)),
+        }
+    }
+}
+
+// The new link is renamed over the old one so that it is never missing.
+fn replace(link: &Path, target: &Path) -> io::Result<()> {
+    let target = match link.parent().map(|dir| target.strip_prefix(dir)) {
+        Some(Ok(relative)) => relative.to_path_buf(),
+        _ => target.to_path_buf(),
+    };
+    let tmp = suffix::tmp_path(link);
+    symlink(target, &tmp)?;
+    fs::rename(&tmp, link)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::file::utils::test_file_at;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_resolve() {
+        let tmp_dir = TempDir::new("example").unwrap();
+        let target = tmp_dir.path().join("pg_2024-05-23.tar");
+        let link = tmp_dir.path().join("latest");
+        fs::File::create(&target).unwrap();
+        symlink("pg_2024-05-23.tar", &link).unwrap();
+        assert!(is_symlink(&link));
+        assert!(!is_symlink(&target));
+        assert_eq!(resolve(&link).unwrap(), target);
+    }
+
+    #[test]
+    fn test_fix_dangling() {
+        let tmp_dir = TempDir::new("example").unwrap();
+        let old = tmp_dir.path().join("pg_2024-05-22.tar");
+        let new = tmp_dir.path().join("pg_2024-05-23.tar");
+        let newest = tmp_dir.path().join("pg_2024-05-24.tar");
+        fs::File::create(&new).unwrap();
+        fs::File::create(&newest).unwrap();
+        let retargeted = tmp_dir.path().join("latest");
+        let flagged = tmp_dir.path().join("previous");
+        let untouched = tmp_dir.path().join("current");
+        symlink("pg_2024-05-22.tar", &retargeted).unwrap();
+        symlink("pg_2024-05-22.tar", &flagged).unwrap();
+        symlink("pg_2024-05-23.tar", &untouched).unwrap();
+
+        let before = vec![test_file_at(&old, 1), test_file_at(&new, 2), test_file_at(&newest, 3)];
+        let kept = vec![test_file_at(&new, 2), test_file_at(&newest, 3)];
+        fix_dangling(std::slice::from_ref(&flagged), &before, &kept, &[], false);
+        fix_dangling(&[retargeted.clone(), untouched.clone()], &before, &kept, &[], true);
+
+        assert_eq!(fs::read_link(&retargeted).unwrap(), Path::new("pg_2024-05-24.tar"));
+        assert_eq!(fs::read_link(&flagged).unwrap(), Path::new("pg_2024-05-22.tar"));
+        assert_eq!(fs::read_link(&untouched).unwrap(), Path::new("pg_2024-05-23.tar"));
+    }
+
+    #[test]
+    fn test_fix_dangling_moved() {
+        let tmp_dir = TempDir::new("example").unwrap();
+        let old = tmp_dir.path().join("pg_2024-05-23.tar");
+        let moved = tmp_dir.path().join("daily/pg_2024-05-23.tar.gz");
+        let newest = tmp_dir.path().join("pg_2024-05-24.tar");
+        fs::create_dir(tmp_dir.path().join("daily")).unwrap();
+        fs::File::create(&moved).unwrap();
+        fs::File::create(&newest).unwrap();
+        let followed = tmp_dir.path().join("latest");
+        let flagged = tmp_dir.path().join("previous");
+        symlink("pg_2024-05-23.tar", &followed).unwrap();
+        symlink("pg_2024-05-23.tar", &flagged).unwrap();
+
+        let before = vec![test_file_at(&old, 1), test_file_at(&newest, 2)];
+        let kept = vec![test_file_at(&moved, 1), test_file_at(&newest, 2)];
+        let renamed = vec![(old.clone(), moved.clone())];
+        fix_dangling(std::slice::from_ref(&followed), &before, &kept, &renamed, true);
+        fix_dangling(std::slice::from_ref(&flagged), &before, &kept, &renamed, false);
+
+        assert_eq!(fs::read_link(&followed).unwrap(), Path::new("daily/pg_2024-05-23.tar.gz"));
+        assert_eq!(fs::read_link(&flagged).unwrap(), Path::new("pg_2024-05-23.tar"));
+    }
+}
@@ -0,0 +1,226 @@
+use std::collections::BTreeSet;
+use std::fs::{read_dir, remove_dir, remove_file, rename, symlink_metadata};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use crate::args::Removal;
+use crate::display::display;
+use crate::execute::prune_empty_parents;
+use crate::suffix;
+
+pub const MARKER: &str = "rotation-deleting";
+pub const BATCH_SIZE: u64 = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Throttle {
+    pub max_unlinks_per_sec: Option<u32>,
+    pub pause_between_batches: Option<Duration>,
+}
+
+impl Throttle {
+    pub fn from_args(removal: &Removal) -> Option<Throttle> {
+        let Removal { max_unlinks_per_sec, pause_between_batches, .. } = *removal;
+        (max_unlinks_per_sec.is_some() || pause_between_batches.is_some())
+            .then_some(Throttle { max_unlinks_per_sec, pause_between_batches })
+    }
+}
+
+pub struct Limiter {
+    throttle: Throttle,
+    next: Option<Instant>,
+    unlinks: u64,
+}
+
+impl Limiter {
+    pub fn new(throttle: Throttle) -> Limiter {
+        Limiter { throttle, next: None, unlinks: 0 }
+    }
+
+    // Spaces unlinks evenly rather than letting time spent elsewhere build up
+    // into a burst. Returns true when a batch has just been completed.
+    fn unlinked(&mut self) -> bool {
+        if let Some(rate) = self.throttle.max_unlinks_per_sec {
+            let now = Instant::now();
+            let due = self.next.unwrap_or(now).max(now);
+            sleep(due - now);
+            self.next = Some(due + Duration::from_secs(1) / rate);
+        }
+        self.unlinks += 1;
+        self.unlinks.is_multiple_of(BATCH_SIZE)
+    }
+
+    fn pause(&self) {
+        if let Some(pause) = self.throttle.pause_between_batches {
+            sleep(pause);
+        }
+    }
+}
+
+// Anything inside a marked directory is part of an interrupted removal too.
+pub fn is_marker(path: &Path) -> bool {
+    marker_of(path).is_some()
+}
+
+// The outermost marked directory that the path is in, or is.
+pub fn marker_of(path: &Path) -> Option<&Path> {
+    path.ancestors().filter(|ancestor| ancestor.extension().is_some_and(|extension| extension == MARKER)).last()
+}
+
+fn marker_path(path: &Path) -> PathBuf {
+    suffix::append(path, MARKER)
+}
+
+pub fn remove(path: &Path, limiter: &mut Limiter) -> io::Result<()> {
+    if !symlink_metadata(path)?.is_dir() {
+        remove_file(path)?;
+        if limiter.unlinked() {
+            limiter.pause();
+        }
+        return Ok(());
+    }
+    // The directory is renamed first, so that a removal that is interrupted
+    // leaves a marked directory that the next run finishes removing, instead
+    // of a partial backup.
+    let marker = marker_path(path);
+    rename(path, &marker)?;
+    remove_marked(&marker, limiter)
+}
+
+fn remove_marked(marker: &Path, limiter: &mut Limiter) -> io::Result<()> {
+    let mut removed = 0;
+    remove_tree(marker, limiter, &mut removed, marker)?;
+    println!("Removed {} entries from {}", removed, display(marker));
+    Ok(())
+}
+
+fn remove_tree(dir: &Path, limiter: &mut Limiter, removed: &mut u64, top: &Path) -> io::Result<()> {
+    for entry in read_dir(dir)? {
+        let entry = entry?;
+        match entry.file_type()?.is_dir() {
+            true => remove_tree(&entry.path(), limiter, removed, top)?,
+            false => {
+                remove_file(entry.path())?;
+                count(limiter, removed, top);
+            }
+        }
+    }
+    remove_dir(dir)?;
+    count(limiter, removed, top);
+    Ok(())
+}
+
+fn count(limiter: &mut Limiter, removed: &mut u64, top: &Path) {
+    *removed += 1;
+    if limiter.unlinked() {
+        println!("Removed {} entries from {} so far", removed, display(top));
+        limiter.pause();
+    }
+}
+
+// Finishes the removals that earlier runs left marked in the directories of
+// the given paths, and removes the directories under `root` that this empties.
+pub fn resume(paths: &[PathBuf], root: Option<&Path>, limiter: &mut Limiter) -> io::Result<()> {
+    let dirs: BTreeSet<&Path> = paths.iter().map(|path| path.parent().unwrap_or(Path::new(""))).collect();
+    for dir in dirs {
+        let listed = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+        let Ok(entries) = read_dir(listed) else { continue };
+        let mut markers: Vec<PathBuf> = entries.filter_map(|entry| entry.ok())
+            .map(|entry| dir.join(entry.file_name()))
+            .filter(|path| is_marker(path) && path.is_dir())
+            .collect();
+        markers.sort();
+        for marker in markers {
+            println!("Resuming removal of {}", display(&marker));
+            remove_marked(&marker, limiter)?;
+            if let Some(root) = root {
+                prune_empty_parents(&marker, root);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs::{create_dir_all, File};
+    use tempdir::TempDir;
+
+    fn snapshot(dir: &Path, files: usize) {
+        create_dir_all(dir.join("base")).unwrap();
+        for i in 0..files {
+            File::create(dir.join("base").join(i.to_string())).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_remove() {
+        let tmp_dir = TempDir::new("example").unwrap();
+        let dir = tmp_dir.path().join("snapshot");
+        snapshot(&dir, 10);
+        let mut limiter = Limiter::new(Throttle::default());
+        remove(&dir, &mut limiter).unwrap();
+
+        assert!(!dir.exists());
+        assert!(!marker_path(&dir).exists());
+        assert_eq!(limiter.unlinks, 12);
+    }
+
+    #[test]
+    fn test_rate() {
+        let tmp_dir = TempDir::new("example").unwrap();
+        let dir = tmp_dir.path().join("snapshot");
+        snapshot(&dir, 9);
+        let mut limiter = Limiter::new(Throttle { max_unlinks_per_sec: Some(50), pause_between_batches: None });
+        let started = Instant::now();
+        remove(&dir, &mut limiter).unwrap();
+
+        assert!(started.elapsed() >= Duration::from_millis(200));
+    }
+
+    #[test]
+    fn test_resume() {
+        let tmp_dir = TempDir::new("example").unwrap();
+        let backup = tmp_dir.path().join("snapshot_2024-05-23");
+        let marker = marker_path(&tmp_dir.path().join("snapshot_2024-05-22"));
+        snapshot(&backup, 2);
+        snapshot(&marker, 2);
+        assert!(is_marker(&marker));
+        assert!(is_marker(&marker.join("base/0")));
+        assert_eq!(marker_of(&marker.join("base/0")), Some(marker.as_path()));
+        assert!(!is_marker(&backup));
+
+        resume(std::slice::from_ref(&backup), None, &mut Limiter::new(Throttle::default())).unwrap();
+        assert!(!marker.exists());
+        assert!(backup.exists());
+    }
+
+    #[test]
+    fn test_resume_nested() {
+        let tmp_dir = TempDir::new("example").unwrap();
+        let backup = tmp_dir.path().join("2024/05/23");
+        let marker = marker_path(&tmp_dir.path().join("2024/05/22"));
+        snapshot(&backup, 2);
+        snapshot(&marker, 2);
+
+        resume(std::slice::from_ref(&backup), None, &mut Limiter::new(Throttle::default())).unwrap();
+        assert!(!marker.exists());
+        assert!(backup.exists());
+    }
+
+    #[test]
+    fn test_resume_without_backups() {
+        let tmp_dir = TempDir::new("example").unwrap();
+        let root = tmp_dir.path().join("backups");
+        let marker = marker_path(&root.join("2024/03/15"));
+        snapshot(&marker, 2);
+        let listed = marker.join("base/0");
+
+        let markers: Vec<PathBuf> = marker_of(&listed).map(Path::to_path_buf).into_iter().collect();
+        resume(&markers, Some(&root), &mut Limiter::new(Throttle::default())).unwrap();
+        assert!(!root.join("2024").exists());
+        assert!(root.exists());
+    }
+}
@@ -0,0 +1,200 @@
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use std::fmt;
+use std::fs::{self, Metadata};
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+use std::os::unix::fs::MetadataExt;
+use std::time::SystemTime;
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum TimeSource {
+    /// Last modification time
+    #[default]
+    Mtime,
+    /// Last status change time
+    Ctime,
+    /// Last access time
+    Atime,
+    /// Creation time, read with statx on Linux
+    Btime,
+    /// Time recorded inside the archive: gzip header, newest tar member or zip manifest
+    Archive,
+    /// Field of a `<backup>.json` or `<backup>.meta` sidecar file
+    Sidecar(String),
+    /// Extended attribute holding an RFC 3339 date or a Unix timestamp
+    Xattr(String),
+}
+
+impl TimeSource {
+    pub fn read(&self, path: &Path, metadata: &Metadata) -> io::Result<SystemTime> {
+        match self {
+            TimeSource::Archive | TimeSource::Sidecar(_) => {
+                unreachable!("{} times are read by File::from_archive and File::from_sidecar", self)
+            }
+            TimeSource::Xattr(name) => {
+                let value = xattr::get(path, name)?
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no {} attribute", name)))?;
+                let value = String::from_utf8_lossy(&value);
+                parse_timestamp(value.trim_end_matches('\0'))
+                    .map(SystemTime::from)
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("invalid {} attribute: {}", name, value)))
+            }
+            TimeSource::Mtime => metadata.modified(),
+            TimeSource::Atime => metadata.accessed(),
+            TimeSource::Btime => metadata.created(),
+            TimeSource::Ctime => {
+                let ctime = DateTime::from_timestamp(metadata.ctime(), metadata.ctime_nsec() as u32);
+                ctime.map(SystemTime::from).ok_or_else(|| io::Error::other("ctime out of range"))
+            }
+        }
+    }
+}
+
+impl FromStr for TimeSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("sidecar", "")) => Err("sidecar requires a key, such as sidecar:snapshot_time".to_string()),
+            Some(("sidecar", key)) => Ok(TimeSource::Sidecar(key.to_string())),
+            Some(("xattr", "")) => Err("xattr requires an attribute name, such as xattr:user.backup.created".to_string()),
+            Some(("xattr", name)) => Ok(TimeSource::Xattr(name.to_string())),
+            _ => match s {
+                "mtime" => Ok(TimeSource::Mtime),
+                "ctime" => Ok(TimeSource::Ctime),
+                "atime" => Ok(TimeSource::Atime),
+                "btime" => Ok(TimeSource::Btime),
+                "archive" => Ok(TimeSource::Archive),
+                _ => Err(format!("unknown time source `{}`, expected mtime, ctime, atime, btime, archive, sidecar:<key> or xattr:<name>", s)),
+            },
+        }
+    }
+}
+
+pub fn parse_timestamp(value: &str) -> Option<DateTime<Utc>> {
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(value) {
+        return Some(timestamp.with_timezone(&Utc));
+    }
+    DateTime::from_timestamp(value.parse().ok()?, 0)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, ValueEnum)]
+pub enum DirTime {
+    /// Timestamp of the directory itself
+    #[default]
+    #[value(name = "self")]
+    Itself,
+    /// Newest timestamp of the files in the directory tree
+    NewestEntry,
+    /// Oldest timestamp of the files in the directory tree
+    OldestEntry,
+}
+
+impl DirTime {
+    pub fn read(&self, path: &Path, source: &TimeSource) -> io::Result<SystemTime> {
+        let metadata = fs::metadata(path)?;
+        if !metadata.is_dir() || *self == DirTime::Itself {
+            return source.read(path, &metadata);
+        }
+        let mut times = vec![];
+        walk(path, source, &mut times)?;
+        let time = match self {
+            DirTime::NewestEntry => times.into_iter().max(),
+            _ => times.into_iter().min(),
+        };
+        // An empty tree has no entry to date it by.
+        match time {
+            Some(time) => Ok(time),
+            None => source.read(path, &metadata),
+        }
+    }
+}
+
+fn walk(dir: &Path, source: &TimeSource, times: &mut Vec<SystemTime>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = fs::symlink_metadata(entry.path())?;
+        match metadata.is_dir() {
+            true => walk(&entry.path(), source, times)?,
+            false => times.push(source.read(&entry.path(), &metadata)?),
+        }
+    }
+    Ok(())
+}
+
+impl fmt::Display for TimeSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TimeSource::Mtime => write!(f, "mtime"),
+            TimeSource::Ctime => write!(f, "ctime"),
+            TimeSource::Atime => write!(f, "atime"),
+            TimeSource::Btime => write!(f, "btime"),
+            TimeSource::Archive => write!(f, "archive"),
+            TimeSource::Sidecar(key) => write!(f, "sidecar:{}", key),
+            TimeSource::Xattr(name) => write!(f, "xattr:{}", name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+    use std::fs;
+    use std::time::Duration;
+
+    #[test]
+    fn test_read() {
+        let tmp_dir = tempdir::TempDir::new("example").unwrap();
+        let path = tmp_dir.path().join("a");
+        fs::File::create(&path).unwrap();
+        let metadata = fs::metadata(&path).unwrap();
+        let now = SystemTime::now();
+        for source in [TimeSource::Mtime, TimeSource::Ctime, TimeSource::Atime] {
+            let time = source.read(&path, &metadata).unwrap();
+            assert!(now.duration_since(time).unwrap() < Duration::from_secs(60));
+        }
+        assert_eq!(TimeSource::Mtime.read(&path, &metadata).unwrap(), metadata.modified().unwrap());
+        assert!(TimeSource::Xattr("user.backup.created".to_string()).read(&path, &metadata).is_err());
+    }
+
+    #[test]
+    fn test_parse_timestamp() {
+        let expected = Utc.with_ymd_and_hms(2024, 5, 23, 3, 0, 1).unwrap();
+        assert_eq!(parse_timestamp("2024-05-23T03:00:01Z"), Some(expected));
+        assert_eq!(parse_timestamp("2024-05-23T05:00:01+02:00"), Some(expected));
+        assert_eq!(parse_timestamp("1716433201"), Some(expected));
+        assert_eq!(parse_timestamp("yesterday"), None);
+    }
+
+    #[test]
+    fn test_from_str() {
+        for source in ["mtime", "ctime", "atime", "btime", "archive", "sidecar:snapshot.time", "xattr:user.backup.created"] {
+            assert_eq!(source.parse::<TimeSource>().unwrap().to_string(), source);
+        }
+        assert_eq!("sidecar:time".parse(), Ok(TimeSource::Sidecar("time".to_string())));
+        assert!("sidecar:".parse::<TimeSource>().is_err());
+        assert!("xattr:".parse::<TimeSource>().is_err());
+        assert!("birth".parse::<TimeSource>().is_err());
+    }
+
+    #[test]
+    fn test_dir_time() {
+        let tmp_dir = tempdir::TempDir::new("example").unwrap();
+        let snapshot = tmp_dir.path().join("snapshot");
+        fs::create_dir_all(snapshot.join("nested")).unwrap();
+        let old = fs::File::create(snapshot.join("old")).unwrap();
+        let new = fs::File::create(snapshot.join("nested/new")).unwrap();
+        let base = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        old.set_modified(base).unwrap();
+        new.set_modified(base + Duration::from_secs(3600)).unwrap();
+
+        assert_eq!(DirTime::NewestEntry.read(&snapshot, &TimeSource::Mtime).unwrap(), base + Duration::from_secs(3600));
+        assert_eq!(DirTime::OldestEntry.read(&snapshot, &TimeSource::Mtime).unwrap(), base);
+        let own = fs::metadata(&snapshot).unwrap().modified().unwrap();
+        assert_eq!(DirTime::Itself.read(&snapshot, &TimeSource::Mtime).unwrap(), own);
+        assert_eq!(DirTime::NewestEntry.read(&snapshot.join("old"), &TimeSource::Mtime).unwrap(), base);
+    }
+}
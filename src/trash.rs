@@ -0,0 +1,109 @@
+use chrono::{DateTime, NaiveDateTime, Utc};
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+
+use crate::display::display;
+use crate::file::File;
+
+pub const DIR: &str = ".rotation-trash";
+// Milliseconds keep two runs started in the same second apart.
+const RUN_ID_FORMAT: &str = "%Y%m%dT%H%M%S%.3fZ";
+
+pub fn run_id(now: DateTime<Utc>) -> String {
+    now.format(RUN_ID_FORMAT).to_string()
+}
+
+// The trash sits next to the backup, or under --root for dated directories,
+// so that moving into it is a rename on the same filesystem.
+pub fn trash_dir(file: &File) -> PathBuf {
+    match &file.root {
+        Some(root) => root.join(DIR),
+        None => file.path.parent().unwrap_or(Path::new("")).join(DIR),
+    }
+}
+
+pub fn trash_path(file: &File, run_id: &str) -> PathBuf {
+    trash_dir(file).join(run_id).join(file.relative_path())
+}
+
+pub fn is_trash(path: &Path) -> bool {
+    path.components().any(|component| component == Component::Normal(DIR.as_ref()))
+}
+
+pub fn trash_dirs(files: &[File]) -> BTreeSet<PathBuf> {
+    files.iter().map(trash_dir).collect()
+}
+
+// Removes the runs older than `grace`. Entries that are not named after a
+// run are left alone.
+pub fn purge(trash_dir: &Path, grace: Duration, now: DateTime<Utc>) -> io::Result<()> {
+    let entries = match fs::read_dir(trash_dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name();
+        let run = match name.to_str().and_then(|name| NaiveDateTime::parse_from_str(name, "%Y%m%dT%H%M%S%.fZ").ok()) {
+            Some(run) => run.and_utc(),
+            None => continue,
+        };
+        if now.signed_duration_since(run).to_std().unwrap_or_default() > grace {
+            println!("Purging trash: {}", display(&entry.path()));
+            fs::remove_dir_all(entry.path())?;
+        }
+    }
+    if fs::read_dir(trash_dir)?.next().is_none() {
+        fs::remove_dir(trash_dir)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::file::utils::test_file;
+    use chrono::TimeZone;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_trash_path() {
+        let now = Utc.with_ymd_and_hms(2024, 5, 23, 3, 0, 1).unwrap();
+        let file = test_file("/backups/pg_2024-05-20.tar", 1);
+        assert_eq!(trash_path(&file, &run_id(now)), Path::new("/backups/.rotation-trash/20240523T030001.000Z/pg_2024-05-20.tar"));
+        let mut dated = test_file("/backups/2024/05/20", 1);
+        dated.root = Some(PathBuf::from("/backups"));
+        assert_eq!(trash_path(&dated, &run_id(now)), Path::new("/backups/.rotation-trash/20240523T030001.000Z/2024/05/20"));
+    }
+
+    #[test]
+    fn test_is_trash() {
+        assert!(is_trash(Path::new("/backups/.rotation-trash")));
+        assert!(is_trash(Path::new("/backups/.rotation-trash/20240523T030001.000Z/a.tar")));
+        assert!(!is_trash(Path::new("/backups/a.tar")));
+    }
+
+    #[test]
+    fn test_purge() {
+        let tmp_dir = TempDir::new("example").unwrap();
+        let trash = tmp_dir.path().join(DIR);
+        fs::create_dir_all(trash.join("20240510T030001Z")).unwrap();
+        fs::write(trash.join("20240510T030001Z/a.tar"), "dump").unwrap();
+        fs::create_dir_all(trash.join("20240522T030001.250Z")).unwrap();
+        fs::create_dir_all(trash.join("keep")).unwrap();
+        let now = Utc.with_ymd_and_hms(2024, 5, 23, 3, 0, 1).unwrap();
+        purge(&trash, Duration::from_secs(7 * 24 * 60 * 60), now).unwrap();
+
+        assert!(!trash.join("20240510T030001Z").exists());
+        assert!(trash.join("20240522T030001.250Z").exists());
+        assert!(trash.join("keep").exists());
+
+        fs::remove_dir(trash.join("keep")).unwrap();
+        purge(&trash, Duration::ZERO, now).unwrap();
+        assert!(!trash.exists());
+    }
+}